@@ -9,11 +9,11 @@ use clap::Parser;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut rsa = RSA::parse();
-    if rsa.output == "stdout" && (rsa.mode == "encode" || rsa.mode == "decode") {
+    if rsa.output == "stdout" && (rsa.mode == "encode" || rsa.mode == "decode" || rsa.mode == "export" || rsa.mode == "keyring-export" || rsa.mode == "pkcs12-export" || rsa.mode == "paper-export") {
         rsa.silent = true;
     }
     if !SILENT.is_set().unwrap() { SILENT.set(rsa.silent).unwrap(); }
-    if !rsa.silent { println!("Run args: {:?}", rsa); }
+    if !rsa.silent { println!("Run args: {:?}", rsa.redacted()); }
     rsa.run()?;
     Ok(())
 }
@@ -21,14 +21,13 @@ fn main() -> Result<(), Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
     use std::error::Error;
-    use std::fs::File;
-    use std::io;
+    use std::io::Cursor;
     use num::Integer;
     use num_bigint::{BigInt, Sign, ToBigInt, ToBigUint};
     use num_traits::One;
     use crate::RSA;
-    use crate::RunMode;
-    use crate::rsa::config::CONFIG_DEF;
+    use crate::{RunMode, PUBLIC_EXPONENT};
+    use crate::rsa::config::{CONFIG_DEF, CONFIG_TEST};
     use crate::rsa::keys::{Key, KeySet};
 
     #[test]
@@ -44,7 +43,7 @@ mod tests {
     #[test]
     fn test_miller_rabin() -> Result<(), Box<dyn Error>> {
         let r = CONFIG_DEF.get();
-        let res = (0xffffff00 as u32..0xffffffff as u32)
+        let res = (0xffffff00_u32..0xffffffff_u32)
             .map(|x| (x, RSA::miller_rabin(&x.to_bigint().unwrap(), r.rounds).unwrap()))
             .filter(|x| x.1)
             .map(|x| x.0)
@@ -81,38 +80,98 @@ mod tests {
 
     #[test]
     fn function_test() -> Result<(), Box<dyn Error>> {
-        let r = CONFIG_DEF.get();
+        let r = CONFIG_TEST.get();
         let keys = r.generate_key()?;
         println!("get keys: {:?}", keys);
         let (key_public, key_private) = (keys.public, keys.private);
-        let mut reader = File::open(&r.input).unwrap();
-        let mut writer_temp = File::create(&r.output).unwrap();
+        let source = "Hello RSA! ".repeat(64);
+        let mut reader = Cursor::new(source.as_bytes());
+        let mut writer_temp = Cursor::new(Vec::new());
         RSA::process(&mut reader, &mut writer_temp, RunMode::Encode, key_public, 1, false);
-        let mut reader_temp = File::open(&r.output).unwrap();
-        let mut writer = io::stdout();
+        let mut reader_temp = Cursor::new(writer_temp.into_inner());
+        let mut writer = Cursor::new(Vec::new());
         RSA::process(&mut reader_temp, &mut writer, RunMode::Decode, key_private, 1, false);
+        assert_eq!(source.as_bytes(), writer.into_inner().as_slice());
         println!("\nDone.");
         Ok(())
     }
 
     #[test]
     fn test_simple_data() -> Result<(), Box<dyn Error>> {
-        let r = CONFIG_DEF.get();
         let (p, q) = (17.to_bigint().unwrap(), 11.to_bigint().unwrap());
         let f = (&q - 1.to_bigint().unwrap()) * (&p - 1.to_bigint().unwrap());
         let e = 7.to_bigint().unwrap();
         let d = RSA::mod_reverse(&e, &f);
         let n = &p * &q;
-        r.check_key_set(&d, &e, &f);
-        let keys = KeySet { public: Key { m: n.clone(), base: e }, private: Key { m: n.clone(), base: d } };
+        RSA::check_key_set(&d, &e, &f)?;
+        let keys = KeySet { public: Key::new(e, n.clone()), private: Key::new(d, n.clone()) };
         println!("keys: {:?}", keys);
         let m = BigInt::from(88);
-        let c = RSA::fast_modular_exponent(m.clone(), keys.public.base, keys.public.m);
-        let m2 = RSA::fast_modular_exponent(c.clone(), keys.private.base, keys.private.m);
+        let c = RSA::fast_modular_exponent(m.clone(), keys.public.base.clone(), keys.public.m.clone());
+        let m2 = RSA::fast_modular_exponent(c.clone(), keys.private.base.clone(), keys.private.m.clone());
         println!("m={}, c={}, m2={}", m, c, m2);
         Ok(())
     }
 
+    #[test]
+    fn test_crt_decode() -> Result<(), Box<dyn Error>> {
+        let r = CONFIG_TEST.get();
+        let keys = r.generate_key()?;
+        let crt = keys.private.crt.clone().unwrap();
+        let plain = Key::new(keys.private.base.clone(), keys.private.m.clone());
+        let m = BigInt::from(0x114514);
+        let c = keys.public.modular_exponent(m.clone());
+        assert_eq!(m, keys.private.modular_exponent(c.clone()));
+        assert_eq!(crt.modular_exponent(c.clone()), plain.modular_exponent(c));
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_prime() -> Result<(), Box<dyn Error>> {
+        let r = CONFIG_TEST.get();
+        let keys = r.generate_key_primes(3)?;
+        let crt = keys.private.crt.clone().unwrap();
+        assert_eq!(crt.others.len(), 1);
+        assert_eq!(crt.primes().iter().product::<BigInt>(), keys.private.m);
+        let source = "Hello multi-prime RSA! ".repeat(32);
+        let mut reader = Cursor::new(source.as_bytes());
+        let mut writer_temp = Cursor::new(Vec::new());
+        RSA::process(&mut reader, &mut writer_temp, RunMode::Encode, keys.public, 2, true);
+        let mut reader_temp = Cursor::new(writer_temp.into_inner());
+        let mut writer = Cursor::new(Vec::new());
+        RSA::process(&mut reader_temp, &mut writer, RunMode::Decode, keys.private, 2, true);
+        assert_eq!(source.as_bytes(), writer.into_inner().as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_bits() -> Result<(), Box<dyn Error>> {
+        let r = RSA { bits: 768, ..CONFIG_TEST.copy() };
+        for count in 2..4 {
+            let keys = r.generate_key_primes(count)?;
+            assert_eq!(keys.public.m.bits(), 768);
+            assert_eq!(keys.public.base, BigInt::from(PUBLIC_EXPONENT));
+        }
+        let r = RSA { bits: 256, ..CONFIG_TEST.copy() };
+        assert!(r.generate_key().is_err());
+        let r = RSA { lab: true, prime_min: 60, prime_max: 64, ..CONFIG_TEST.copy() };
+        let keys = r.generate_key()?;
+        assert!(keys.public.m.bits() <= 128);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seeded_key() -> Result<(), Box<dyn Error>> {
+        let seed = [7u8; 32];
+        let a = RSA { threads: 1, ..CONFIG_TEST.copy() }.generate_key_seeded(&seed)?;
+        let b = RSA { threads: 4, ..CONFIG_TEST.copy() }.generate_key_seeded(&seed)?;
+        assert_eq!(a.public, b.public);
+        assert_eq!(a.private.crt, b.private.crt);
+        let c = CONFIG_TEST.get().generate_key_seeded(&[8u8; 32])?;
+        assert_ne!(a.public.m, c.public.m);
+        Ok(())
+    }
+
     #[test]
     fn test_vec_push() {
         let mut v = vec![1, 2, 3, 4];
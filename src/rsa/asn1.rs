@@ -0,0 +1,290 @@
+use num_bigint::BigInt;
+use crate::rsa::keys::KeyError;
+
+pub const TAG_BOOLEAN: u8 = 0x01;
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_NULL: u8 = 0x05;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTF8_STRING: u8 = 0x0c;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+pub const TAG_BMP_STRING: u8 = 0x1e;
+/// Constructed, context specific `[n]`
+pub const TAG_CONTEXT: u8 = 0xa0;
+
+/// One ASN.1 value, encoded with the distinguished encoding rules of X.690
+#[derive(Debug, Clone, PartialEq)]
+pub enum Der {
+    Boolean(bool),
+    Integer(BigInt),
+    /// Bit string with no unused bits
+    BitString(Vec<u8>),
+    OctetString(Vec<u8>),
+    Null,
+    Oid(Vec<u64>),
+    Utf8String(String),
+    BmpString(String),
+    Sequence(Vec<Der>),
+    Set(Vec<Der>),
+    /// Explicitly tagged `[n]` value
+    Context(u8, Vec<Der>),
+    /// Any other tag, kept as its raw content
+    Raw(u8, Vec<u8>),
+}
+
+/// Deepest nesting of constructed values, keys need a few levels and PKCS#12 files about ten
+const MAX_DEPTH: usize = 32;
+
+fn der_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("ASN.1: {}", msg))
+}
+
+fn encode_length(len: usize, res: &mut Vec<u8>) {
+    if len < 0x80 {
+        res.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        res.push(0x80 | (bytes.len() - skip) as u8);
+        res.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+fn encode_oid(oid: &[u64]) -> Vec<u8> {
+    let mut res = Vec::new();
+    let mut arcs = vec![oid[0] * 40 + oid[1]];
+    arcs.extend_from_slice(&oid[2..]);
+    for arc in arcs {
+        let mut bytes = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest != 0 {
+            bytes.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        bytes.reverse();
+        res.extend(bytes);
+    }
+    res
+}
+
+fn decode_oid(data: &[u8]) -> Result<Vec<u64>, KeyError> {
+    let mut arcs = Vec::new();
+    let mut arc: u64 = 0;
+    for (i, b) in data.iter().enumerate() {
+        if arc == 0 && *b == 0x80 { return Err(der_error("OID arc is not minimal")); }
+        if arc >> 57 != 0 { return Err(der_error("OID arc too large")); }
+        arc = (arc << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        } else if i + 1 == data.len() {
+            return Err(der_error("OID is truncated"));
+        }
+    }
+    let first = *arcs.first().ok_or_else(|| der_error("empty OID"))?;
+    let mut res = match first {
+        0..=39 => vec![0, first],
+        40..=79 => vec![1, first - 40],
+        _ => vec![2, first - 80],
+    };
+    res.extend_from_slice(&arcs[1..]);
+    Ok(res)
+}
+
+/// Dotted form of an OID, e.g. `1.2.840.113549.1.1.1`
+pub fn oid_string(oid: &[u64]) -> String {
+    oid.iter().map(|arc| arc.to_string()).collect::<Vec<_>>().join(".")
+}
+
+impl Der {
+    pub fn tag(&self) -> u8 {
+        match self {
+            Der::Boolean(_) => TAG_BOOLEAN,
+            Der::Integer(_) => TAG_INTEGER,
+            Der::BitString(_) => TAG_BIT_STRING,
+            Der::OctetString(_) => TAG_OCTET_STRING,
+            Der::Null => TAG_NULL,
+            Der::Oid(_) => TAG_OID,
+            Der::Utf8String(_) => TAG_UTF8_STRING,
+            Der::BmpString(_) => TAG_BMP_STRING,
+            Der::Sequence(_) => TAG_SEQUENCE,
+            Der::Set(_) => TAG_SET,
+            Der::Context(n, _) => TAG_CONTEXT | n,
+            Der::Raw(tag, _) => *tag,
+        }
+    }
+
+    fn content(&self) -> Vec<u8> {
+        match self {
+            Der::Boolean(b) => vec![if *b { 0xff } else { 0 }],
+            Der::Integer(i) => i.to_signed_bytes_be(),
+            Der::BitString(bits) => [&[0u8], bits.as_slice()].concat(),
+            Der::OctetString(data) | Der::Raw(_, data) => data.clone(),
+            Der::Null => vec![],
+            Der::Oid(oid) => encode_oid(oid),
+            Der::Utf8String(s) => s.as_bytes().to_vec(),
+            Der::BmpString(s) => s.encode_utf16().flat_map(|c| c.to_be_bytes()).collect(),
+            Der::Sequence(items) | Der::Context(_, items) => items.iter().flat_map(|i| i.to_der()).collect(),
+            Der::Set(items) => {
+                // DER sorts the elements of a SET OF by their encoding
+                let mut items = items.iter().map(|i| i.to_der()).collect::<Vec<_>>();
+                items.sort();
+                items.concat()
+            }
+        }
+    }
+
+    pub fn to_der(&self) -> Vec<u8> {
+        let content = self.content();
+        let mut res = vec![self.tag()];
+        encode_length(content.len(), &mut res);
+        res.extend(content);
+        res
+    }
+
+    /// Decode one value from the front of `data`, returning the bytes after it
+    pub fn parse(data: &[u8]) -> Result<(Der, &[u8]), KeyError> {
+        Der::parse_at(data, 0)
+    }
+
+    /// `parse` for a value inside `depth` constructed values
+    fn parse_at(data: &[u8], depth: usize) -> Result<(Der, &[u8]), KeyError> {
+        if data.len() < 2 { return Err(KeyError::Truncated); }
+        let tag = data[0];
+        if tag & 0x1f == 0x1f { return Err(der_error("high tag numbers are not supported")); }
+        let (len, header) = match data[1] {
+            0x80 => return Err(der_error("indefinite length is not DER")),
+            l if l < 0x80 => (l as usize, 2),
+            l => {
+                let n = (l & 0x7f) as usize;
+                if n > 4 || data.len() < 2 + n { return Err(der_error("bad length")); }
+                let len = data[2..2 + n].iter().fold(0usize, |a, b| (a << 8) | *b as usize);
+                if len < 0x80 || data[2] == 0 { return Err(der_error("length is not minimal")); }
+                (len, 2 + n)
+            }
+        };
+        if data.len() - header < len { return Err(KeyError::Truncated); }
+        let content = &data[header..header + len];
+        let rest = &data[header + len..];
+        let value = match tag {
+            TAG_BOOLEAN => match content {
+                [0] => Der::Boolean(false),
+                [0xff] => Der::Boolean(true),
+                _ => return Err(der_error("bad boolean")),
+            },
+            TAG_INTEGER => {
+                if content.is_empty() { return Err(der_error("empty integer")); }
+                if content.len() > 1 && ((content[0] == 0 && content[1] & 0x80 == 0) || (content[0] == 0xff && content[1] & 0x80 != 0)) {
+                    return Err(der_error("integer is not minimal"));
+                }
+                Der::Integer(BigInt::from_signed_bytes_be(content))
+            }
+            TAG_BIT_STRING => match content.split_first() {
+                Some((0, bits)) => Der::BitString(bits.to_vec()),
+                _ => return Err(der_error("bit strings with unused bits are not supported")),
+            },
+            TAG_OCTET_STRING => Der::OctetString(content.to_vec()),
+            TAG_NULL => match content.is_empty() {
+                true => Der::Null,
+                false => return Err(der_error("bad null")),
+            },
+            TAG_OID => Der::Oid(decode_oid(content)?),
+            TAG_UTF8_STRING => Der::Utf8String(String::from_utf8(content.to_vec()).map_err(|_| der_error("bad UTF-8 string"))?),
+            TAG_BMP_STRING => {
+                if !content.len().is_multiple_of(2) { return Err(der_error("bad BMP string")); }
+                let units = content.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<_>>();
+                Der::BmpString(String::from_utf16(&units).map_err(|_| der_error("bad BMP string"))?)
+            }
+            TAG_SEQUENCE => Der::Sequence(Der::parse_all(content, depth + 1)?),
+            TAG_SET => Der::Set(Der::parse_all(content, depth + 1)?),
+            t if t & 0xe0 == TAG_CONTEXT => Der::Context(t & 0x1f, Der::parse_all(content, depth + 1)?),
+            t => Der::Raw(t, content.to_vec()),
+        };
+        Ok((value, rest))
+    }
+
+    fn parse_all(mut data: &[u8], depth: usize) -> Result<Vec<Der>, KeyError> {
+        if depth > MAX_DEPTH { return Err(der_error("values are nested too deeply")); }
+        let mut items = Vec::new();
+        while !data.is_empty() {
+            let (item, rest) = Der::parse_at(data, depth)?;
+            items.push(item);
+            data = rest;
+        }
+        Ok(items)
+    }
+
+    /// Decode a whole DER document holding exactly one value
+    pub fn from_der(data: &[u8]) -> Result<Der, KeyError> {
+        let (value, rest) = Der::parse(data)?;
+        if !rest.is_empty() { return Err(der_error("trailing data")); }
+        Ok(value)
+    }
+
+    pub fn as_sequence(&self) -> Result<&[Der], KeyError> {
+        match self {
+            Der::Sequence(items) => Ok(items),
+            _ => Err(der_error("expect SEQUENCE")),
+        }
+    }
+
+    pub fn as_integer(&self) -> Result<&BigInt, KeyError> {
+        match self {
+            Der::Integer(i) => Ok(i),
+            _ => Err(der_error("expect INTEGER")),
+        }
+    }
+
+    pub fn as_octet_string(&self) -> Result<&[u8], KeyError> {
+        match self {
+            Der::OctetString(data) => Ok(data),
+            _ => Err(der_error("expect OCTET STRING")),
+        }
+    }
+
+    pub fn as_bit_string(&self) -> Result<&[u8], KeyError> {
+        match self {
+            Der::BitString(data) => Ok(data),
+            _ => Err(der_error("expect BIT STRING")),
+        }
+    }
+
+    pub fn as_oid(&self) -> Result<&[u64], KeyError> {
+        match self {
+            Der::Oid(oid) => Ok(oid),
+            _ => Err(der_error("expect OBJECT IDENTIFIER")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::rsa::asn1::Der;
+
+    #[test]
+    fn test_der() {
+        let value = Der::Sequence(vec![
+            Der::Integer(BigInt::from(0)),
+            Der::Integer(BigInt::from(128)),
+            Der::Integer(BigInt::from(-129)),
+            Der::Oid(vec![1, 2, 840, 113549, 1, 1, 1]),
+            Der::Null,
+            Der::OctetString(vec![0x42; 300]),
+            Der::Context(0, vec![Der::BmpString("rsa-rs".to_string())]),
+        ]);
+        let der = value.to_der();
+        assert_eq!(&der[..4], &[0x30, 0x82, 0x01, 0x58]);
+        assert_eq!(&der[4..14], &[0x02, 0x01, 0x00, 0x02, 0x02, 0x00, 0x80, 0x02, 0x02, 0xff]);
+        assert_eq!(Der::Oid(vec![1, 2, 840, 113549, 1, 1, 1]).to_der(), vec![0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01]);
+        assert_eq!(Der::from_der(&der).unwrap(), value);
+        assert!(Der::from_der(&der[..der.len() - 1]).is_err());
+        assert!(Der::from_der(&[0x02, 0x02, 0x00, 0x01]).is_err());
+        assert!(Der::from_der(&[0x30, 0x80, 0x00, 0x00]).is_err());
+        let nested = |depth: usize| (0..depth).fold(Der::Null, |value, _| Der::Sequence(vec![value])).to_der();
+        assert!(Der::from_der(&nested(32)).is_ok());
+        assert!(Der::from_der(&nested(33)).is_err());
+    }
+}
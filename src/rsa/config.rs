@@ -7,21 +7,55 @@ lazy_static! {
     pub static ref CONFIG_DEF: RSA = RSA {
         mode: String::from("generate"),
         key: String::from("key"),
+        keyring: String::new(),
+        name: String::new(),
         input: String::from("stdin"),
         // input: String::from("data/lab2-Plaintext.txt"),
         output: String::from("stdout"),
         // output: String::from("data/data.tmp"),
+        bits: 2048, min_bits: 2048,
+        lab: false,
         prime_min: 14, prime_max: 512,
+        primes: 2,
         binary: false,
+        format: String::from("native"),
         rounds: 10,
         time_max: 1000,
         silent: false,
         threads: num_cpus::get(),
         retry: true,
-        comment: String::from("RSA-RS COMMENT")
+        comment: String::from("RSA-RS COMMENT"),
+        passphrase_env: String::new(),
+        passphrase_fd: None,
+        new_passphrase_env: String::new(),
+        no_passphrase: false,
+        seed: String::new(),
+        mnemonic: String::new(),
+        modulus: String::new(),
+        public_exponent: String::new(),
+        private_exponent: String::new(),
+        prime: vec![],
+        number_format: String::from("dec"),
+        cert: String::new(),
+        paper_encoding: String::from("hex"),
+        owner: String::new(),
+        expires: String::new(),
+        usages: String::from("encrypt,decrypt,sign,verify"),
+        ignore_metadata: false,
+        force: false,
+    };
+    /// Small keys so that tests run quickly in debug builds
+    pub static ref CONFIG_TEST: RSA = RSA {
+        bits: 512, min_bits: 512,
+        silent: true,
+        ..CONFIG_DEF.copy()
     };
     pub static ref SILENT: MutStatic<bool> =
         MutStatic::new();
         // MutStatic::from(false);
         // MutStatic::from(true);
 }
+
+pub fn silent() -> bool {
+    SILENT.read().map(|s| *s).unwrap_or(false)
+}
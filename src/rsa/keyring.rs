@@ -0,0 +1,341 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+use crate::rsa::keys::{Key, KeyData, KeyError, KeyFormat};
+use crate::rsa::safe_file::{check_overwrite, SafeFile};
+use crate::RSA;
+
+/// Index of a keyring directory, next to the key files named by their fingerprint
+pub const KEYRING_INDEX: &str = "index.json";
+const KEYRING_VERSION: u64 = 1;
+/// Keys on the command line that start with this are looked up in the keyring
+pub const KEYRING_PREFIX: char = '@';
+
+fn keyring_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("keyring: {}", msg))
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> KeyError {
+    KeyError::Io(format!("{}: {}", path.display(), e))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyringEntry {
+    pub name: String,
+    /// SHA-256 of the public key, see `KeyData::fingerprint`
+    pub fingerprint: Vec<u8>,
+    /// Whether the private key is stored too
+    pub private: bool,
+}
+
+impl KeyringEntry {
+    /// Lowercase hex of the fingerprint, also the name of the key files
+    pub fn id(&self) -> String {
+        self.fingerprint.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn fingerprint_openssh(&self) -> String {
+        format!("SHA256:{}", base64::encode_config(&self.fingerprint, base64::STANDARD_NO_PAD))
+    }
+
+    /// `reference` is a prefix of the hex or the `SHA256:` form of the fingerprint
+    fn matches_fingerprint(&self, reference: &str) -> bool {
+        let hex = reference.replace(':', "").to_lowercase();
+        (reference.starts_with("SHA256:") && self.fingerprint_openssh().starts_with(reference))
+            || (!hex.is_empty() && self.id().starts_with(&hex))
+    }
+}
+
+/// Named keys in a directory: `<id>` and `<id>.pub` per key as they were imported, and `index.json`
+pub struct Keyring {
+    dir: PathBuf,
+    pub entries: Vec<KeyringEntry>,
+    /// Fingerprint of the key `@` refers to
+    pub default: Option<Vec<u8>>,
+}
+
+impl Keyring {
+    /// Keyring in `dir`, empty when the directory does not exist yet
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, KeyError> {
+        let dir = dir.as_ref().to_path_buf();
+        let path = dir.join(KEYRING_INDEX);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self { dir, entries: vec![], default: None }),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        let index: Value = serde_json::from_str(&text).map_err(|e| keyring_error(&e.to_string()))?;
+        match index.get("version").and_then(|v| v.as_u64()) {
+            Some(KEYRING_VERSION) => {}
+            version => return Err(keyring_error(&format!("unsupported index version {:?}", version))),
+        }
+        let fingerprint = |value: Option<&Value>| -> Result<Vec<u8>, KeyError> {
+            let hex = value.and_then(|v| v.as_str()).ok_or_else(|| keyring_error("entry without fingerprint"))?;
+            (0..hex.len()).step_by(2)
+                .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| keyring_error(&format!("bad fingerprint {}", hex)))
+        };
+        let entries = index.get("keys").and_then(|k| k.as_array()).ok_or_else(|| keyring_error("index without keys"))?
+            .iter().map(|entry| Ok(KeyringEntry {
+                name: entry.get("name").and_then(|n| n.as_str()).ok_or_else(|| keyring_error("entry without name"))?.to_string(),
+                fingerprint: fingerprint(entry.get("fingerprint"))?,
+                private: entry.get("private").and_then(|p| p.as_bool()).unwrap_or(false),
+            }))
+            .collect::<Result<Vec<_>, KeyError>>()?;
+        let default = match index.get("default") {
+            None | Some(Value::Null) => None,
+            value => Some(fingerprint(value)?),
+        };
+        Ok(Self { dir, entries, default })
+    }
+
+    fn save(&self) -> Result<(), KeyError> {
+        let id = |fingerprint: &Vec<u8>| fingerprint.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let index = json!({
+            "version": KEYRING_VERSION,
+            "default": self.default.as_ref().map(id),
+            "keys": self.entries.iter().map(|entry| json!({
+                "name": entry.name,
+                "fingerprint": entry.id(),
+                "private": entry.private,
+            })).collect::<Vec<_>>(),
+        });
+        self.write(KEYRING_INDEX, (serde_json::to_string_pretty(&index).unwrap() + "\n").as_bytes(), false)
+    }
+
+    fn write(&self, name: &str, data: &[u8], private: bool) -> Result<(), KeyError> {
+        fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        let path = self.dir.join(name);
+        let mut file = SafeFile::create(&path, private).map_err(|e| io_error(&path, e))?;
+        file.write_all(data).map_err(|e| io_error(&path, e))?;
+        file.commit().map_err(|e| io_error(&path, e))
+    }
+
+    /// Path of the private key of `entry`, the public key is at this path and `.pub`
+    pub fn key_path(&self, entry: &KeyringEntry) -> String {
+        self.dir.join(entry.id()).to_string_lossy().to_string()
+    }
+
+    /// The key called `reference`, else the only one whose fingerprint starts with it, the default key for ""
+    pub fn find(&self, reference: &str) -> Result<&KeyringEntry, KeyError> {
+        if reference.is_empty() {
+            let default = self.default.as_ref().ok_or_else(|| KeyError::NotFound("default keyring key, set one with mode `keyring-set-default'".to_string()))?;
+            return self.entries.iter().find(|e| &e.fingerprint == default).ok_or_else(|| keyring_error("default key is missing"));
+        }
+        if let Some(entry) = self.entries.iter().find(|e| e.name == reference) {
+            return Ok(entry);
+        }
+        let found = self.entries.iter().filter(|e| e.matches_fingerprint(reference)).collect::<Vec<_>>();
+        match found.as_slice() {
+            [entry] => Ok(entry),
+            [] => Err(KeyError::NotFound(format!("{}{} in keyring {}", KEYRING_PREFIX, reference, self.dir.display()))),
+            _ => Err(keyring_error(&format!("{} matches {}", reference, found.iter().map(|e| e.name.as_str()).collect::<Vec<_>>().join(", ")))),
+        }
+    }
+
+    /// Store the key files as they are under `name`, replacing a key of the same name or fingerprint only when `force`
+    pub fn import(&mut self, name: &str, fingerprint: Vec<u8>, public: &[u8], private: Option<&[u8]>, force: bool) -> Result<KeyringEntry, KeyError> {
+        if name.is_empty() || name.starts_with(KEYRING_PREFIX) {
+            return Err(keyring_error(&format!("bad key name `{}'", name)));
+        }
+        let existing = self.entries.iter().filter(|e| e.name == name || e.fingerprint == fingerprint).collect::<Vec<_>>();
+        if !force {
+            if let Some(entry) = existing.first() {
+                return Err(keyring_error(&format!("key {} is already there as {}, use --force to replace it", entry.fingerprint_openssh(), entry.name)));
+            }
+        }
+        let stale = existing.into_iter().cloned().collect::<Vec<_>>();
+        let entry = KeyringEntry { name: name.to_string(), fingerprint, private: private.is_some() };
+        self.write(&(entry.id() + ".pub"), public, false)?;
+        match private {
+            Some(private) => self.write(&entry.id(), private, true)?,
+            None => { let _ = fs::remove_file(self.key_path(&entry)); }
+        }
+        for old in stale.iter().filter(|old| old.fingerprint != entry.fingerprint) {
+            self.remove_files(old);
+            if self.default.as_ref() == Some(&old.fingerprint) { self.default = Some(entry.fingerprint.clone()); }
+        }
+        self.entries.retain(|e| e.name != name && e.fingerprint != entry.fingerprint);
+        self.entries.push(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    fn remove_files(&self, entry: &KeyringEntry) {
+        let path = self.key_path(entry);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path + ".pub");
+    }
+
+    pub fn remove(&mut self, reference: &str) -> Result<KeyringEntry, KeyError> {
+        let entry = self.find(reference)?.clone();
+        self.entries.retain(|e| e.fingerprint != entry.fingerprint);
+        if self.default.as_ref() == Some(&entry.fingerprint) { self.default = None; }
+        self.save()?;
+        self.remove_files(&entry);
+        Ok(entry)
+    }
+
+    pub fn set_default(&mut self, reference: &str) -> Result<KeyringEntry, KeyError> {
+        let entry = self.find(reference)?.clone();
+        self.default = Some(entry.fingerprint.clone());
+        self.save()?;
+        Ok(entry)
+    }
+}
+
+impl RSA {
+    /// `--keyring`, or `.rsa-rs/keyring` in the home directory
+    pub fn keyring(&self) -> Result<Keyring, KeyError> {
+        match self.keyring.is_empty() {
+            true => {
+                let home = std::env::var("HOME").map_err(|_| keyring_error("HOME is not set, pass --keyring"))?;
+                Keyring::open(Path::new(&home).join(".rsa-rs").join("keyring"))
+            }
+            false => Keyring::open(&self.keyring),
+        }
+    }
+
+    /// Point `--key` at the keyring files when it is `@name`, `@<fingerprint prefix>` or `@` for the default key
+    pub fn resolve_key(&mut self) -> Result<(), KeyError> {
+        if let Some(reference) = self.key.strip_prefix(KEYRING_PREFIX) {
+            let keyring = self.keyring()?;
+            self.key = keyring.key_path(keyring.find(reference)?);
+        }
+        Ok(())
+    }
+
+    /// Reference of the keyring modes, `--key` with or without the `@`
+    pub fn keyring_reference(&self) -> &str {
+        self.key.strip_prefix(KEYRING_PREFIX).unwrap_or(&self.key)
+    }
+
+    /// Copy `--key` and `--key.pub` into the keyring as `--name`, the file name of `--key` by default.
+    /// A missing public key file is made from the private key, a present one must belong to it.
+    pub fn keyring_import(&self) -> Result<KeyringEntry, KeyError> {
+        let read = |path: &str| match fs::read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(KeyError::Io(format!("{}: {}", path, e))),
+        };
+        let path_public = self.key.clone() + ".pub";
+        let (public, private) = (read(&path_public)?, read(&self.key)?);
+        let (fingerprint, public) = match (public, &private) {
+            (Some(public), _) => {
+                let fingerprint = self.load_key(path_public.clone())?.fingerprint()?;
+                if private.is_some() && self.load_key(self.key.clone())?.fingerprint()? != fingerprint {
+                    return Err(KeyError::InvalidKey(format!("{} does not belong to the private key {}", path_public, self.key)));
+                }
+                (fingerprint, public)
+            }
+            (None, Some(_)) => {
+                let private = self.load_key(self.key.clone())?;
+                let (e, n) = private.public_numbers()?;
+                let mut public = KeyData::new_public(Key::new(e.clone(), n.clone()), private.comment.clone());
+                public.metadata = private.metadata.clone();
+                (private.fingerprint()?, public.to_pem_string(&KeyFormat::Native)?.into_bytes())
+            }
+            (None, None) => return Err(KeyError::NotFound(format!("{}, {}", self.key, path_public))),
+        };
+        let name = match self.name.is_empty() {
+            true => Path::new(&self.key).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            false => self.name.clone(),
+        };
+        self.keyring()?.import(&name, fingerprint, &public, private.as_deref(), self.force)
+    }
+
+    /// Copy the files of a keyring key to `--output` and `--output.pub`, or print the public key for stdout
+    pub fn keyring_export(&self) -> Result<KeyringEntry, KeyError> {
+        let keyring = self.keyring()?;
+        let entry = keyring.find(self.keyring_reference())?.clone();
+        let path = keyring.key_path(&entry);
+        let copy = |from: &str, to: &str, private: bool| -> Result<(), KeyError> {
+            let data = fs::read(from).map_err(|e| KeyError::Io(format!("{}: {}", from, e)))?;
+            check_overwrite(to, self.force).map_err(|e| KeyError::Io(e.to_string()))?;
+            let mut file = SafeFile::create(to, private).map_err(|e| KeyError::Io(format!("{}: {}", to, e)))?;
+            file.write_all(&data).and_then(|_| file.commit()).map_err(|e| KeyError::Io(format!("{}: {}", to, e)))
+        };
+        match self.output.as_str() {
+            "stdout" => {
+                let data = fs::read(path.clone() + ".pub").map_err(|e| KeyError::Io(format!("{}.pub: {}", path, e)))?;
+                std::io::stdout().write_all(&data).map_err(|e| KeyError::Io(e.to_string()))?;
+            }
+            output => {
+                copy(&(path.clone() + ".pub"), &(output.to_string() + ".pub"), false)?;
+                if entry.private { copy(&path, output, true)?; }
+            }
+        }
+        Ok(entry)
+    }
+
+    pub fn keyring_list(&self) -> Result<(), KeyError> {
+        let keyring = self.keyring()?;
+        if keyring.entries.is_empty() && !self.silent { println!("No keys in keyring {}", keyring.dir.display()); }
+        let width = keyring.entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+        for entry in &keyring.entries {
+            let default = if keyring.default.as_ref() == Some(&entry.fingerprint) { "*" } else { " " };
+            let kind = if entry.private { "key pair" } else { "public key" };
+            println!("{} {:width$}  {}  {}", default, entry.name, entry.fingerprint_openssh(), kind, width = width);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::fs;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keyring::Keyring;
+    use crate::rsa::keys::{KeyData, KeyError, KeyPair};
+
+    #[test]
+    fn test_keyring() -> Result<(), Box<dyn Error>> {
+        let dir = "data/test_keyring";
+        let _ = fs::remove_dir_all(dir);
+        let mut rsa = CONFIG_TEST.get().copy();
+        rsa.keyring = dir.to_string();
+        let mut fingerprints = vec![];
+        for name in ["alice", "bob"] {
+            let key_set = rsa.generate_key()?;
+            let mut key_pair = KeyPair {
+                public: KeyData::new_public(key_set.public, name.to_string()),
+                private: KeyData::new_private(key_set.private, name.to_string()),
+            };
+            let path = format!("data/test_keyring_{}", name);
+            key_pair.save(path.clone(), true)?;
+            rsa.key = path.clone();
+            rsa.name = name.to_string();
+            let entry = rsa.keyring_import()?;
+            assert!(entry.private);
+            assert!(matches!(rsa.keyring_import(), Err(KeyError::ParseError(_))));
+            fingerprints.push(key_pair.public.fingerprint()?);
+        }
+        fs::copy("data/test_keyring_alice", "data/test_keyring_mixed")?;
+        fs::copy("data/test_keyring_bob.pub", "data/test_keyring_mixed.pub")?;
+        rsa.key = "data/test_keyring_mixed".to_string();
+        rsa.name = "mixed".to_string();
+        assert!(matches!(rsa.keyring_import(), Err(KeyError::InvalidKey(_))));
+        let mut keyring = Keyring::open(dir)?;
+        assert_eq!(keyring.entries.len(), 2);
+        assert_eq!(keyring.find("bob")?.fingerprint, fingerprints[1]);
+        let alice = keyring.find("alice")?.clone();
+        assert_eq!(keyring.find(&alice.id()[..12])?, &alice);
+        assert_eq!(keyring.find(&alice.fingerprint_openssh()[..16])?, &alice);
+        assert!(matches!(keyring.find("carol"), Err(KeyError::NotFound(_))));
+        assert!(keyring.find("").is_err());
+        keyring.set_default("alice")?;
+
+        rsa.key = "@".to_string();
+        rsa.resolve_key()?;
+        assert_eq!(KeyPair::load(rsa.key.clone())?.public.fingerprint()?, fingerprints[0]);
+        let mut keyring = Keyring::open(dir)?;
+        keyring.remove("alice")?;
+        assert!(keyring.default.is_none());
+        assert!(fs::metadata(keyring.key_path(&alice)).is_err());
+        assert_eq!(Keyring::open(dir)?.entries.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use num_traits::Zero;
+use sha2::{Digest, Sha256};
+use crate::rsa::keys::KeyError;
+use crate::rsa::keys::key_data::KeyData;
+
+/// Size of the randomart field, as in OpenSSH
+const FIELD_WIDTH: usize = 17;
+const FIELD_HEIGHT: usize = 9;
+/// Symbols by how often the bishop visited a cell, the last two mark start and end
+const SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+impl KeyData {
+    /// SHA-256 over the `ssh-rsa` public key blob, so it matches `ssh-keygen -l`
+    pub fn fingerprint(&self) -> Result<Vec<u8>, KeyError> {
+        if self.key.m.is_zero() { return Err(KeyError::InvalidKey("no key".to_string())); }
+        Ok(Sha256::digest(self.ssh_public_blob()?).to_vec())
+    }
+
+    /// `SHA256:` and unpadded base64, the form OpenSSH shows
+    pub fn fingerprint_openssh(&self) -> Result<String, KeyError> {
+        Ok(format!("SHA256:{}", base64::encode_config(self.fingerprint()?, base64::STANDARD_NO_PAD)))
+    }
+
+    /// Colon separated hex bytes
+    pub fn fingerprint_hex(&self) -> Result<String, KeyError> {
+        Ok(self.fingerprint()?.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":"))
+    }
+
+    pub fn print_fingerprint(&self) {
+        match (self.fingerprint_openssh(), self.fingerprint_hex(), self.randomart()) {
+            (Ok(fingerprint), Ok(hex), Ok(art)) => {
+                println!("fingerprint: {}", fingerprint);
+                println!("fingerprint hex: {}", hex);
+                print!("{}", art);
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => println!("fingerprint unavailable: {}", e),
+        }
+    }
+
+    /// The drunken bishop walk of OpenSSH's `sshkey_fingerprint_randomart` over the fingerprint
+    pub fn randomart(&self) -> Result<String, KeyError> {
+        let mut field = [[0usize; FIELD_HEIGHT]; FIELD_WIDTH];
+        let (mut x, mut y) = (FIELD_WIDTH / 2, FIELD_HEIGHT / 2);
+        let end = SYMBOLS.len() - 1;
+        for byte in self.fingerprint()? {
+            let mut input = byte;
+            for _ in 0..4 {
+                x = if input & 1 != 0 { (x + 1).min(FIELD_WIDTH - 1) } else { x.saturating_sub(1) };
+                y = if input & 2 != 0 { (y + 1).min(FIELD_HEIGHT - 1) } else { y.saturating_sub(1) };
+                if field[x][y] < end - 2 { field[x][y] += 1; }
+                input >>= 2;
+            }
+        }
+        field[FIELD_WIDTH / 2][FIELD_HEIGHT / 2] = end - 1;
+        field[x][y] = end;
+        let border = |title: String| {
+            let left = (FIELD_WIDTH - title.len()) / 2;
+            format!("+{}{}{}+\n", "-".repeat(left), title, "-".repeat(FIELD_WIDTH - left - title.len()))
+        };
+        let mut art = border(format!("[RSA {}]", self.key.m.bits()));
+        for y in 0..FIELD_HEIGHT {
+            let row = (0..FIELD_WIDTH).map(|x| SYMBOLS[field[x][y]] as char).collect::<String>();
+            art += &format!("|{}|\n", row);
+        }
+        art += &border("[SHA256]".to_string());
+        Ok(art)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use num_traits::Num;
+    use crate::rsa::keys::Key;
+    use crate::rsa::keys::key_data::KeyData;
+
+    #[test]
+    fn test_fingerprint() -> Result<(), Box<dyn Error>> {
+        // Output of `ssh-keygen -lv` for the key of RFC 7638 section 3.1
+        let n = BigInt::from_str_radix(concat!(
+            "d2fc7b6a0a1e6c67104aeb8f88b257669b4df679ddad099b5c4a6cd9a88015b5a133bf0b856c7871b6df000b554fceb3",
+            "c2ed512bb68f145c6e8434752fab52a1cfc124408f79b58a4578c16428855789f7a249e384cb2d9fae2d67fd96fb926c",
+            "198e077399fdc815c0af097dde5aadeff44de70e827f4878432439bfeeb96068d0474fc50d6d90bf3a98dfaf1040c89c",
+            "02d692ab3b3c2896609d86fd73b774ce0740647ceeeaa310bd12f985a8eb9f59fdd426cea5b2120f4f2a34bcab764b7e",
+            "6c54d6840238bcc40587a59e66ed1f33894577635c470af75cf92c20d1da43e1bfc419e222a6f0d0bb358c5e38f9cb05",
+            "0aeafe904814f1ac1aa49cca9ea0ca83",
+        ), 16)?;
+        let key = KeyData::new_public(Key::new(BigInt::from(65537), n), "".to_string());
+        assert_eq!(key.fingerprint_openssh()?, "SHA256:h+PAyXb3n4bqtmzZtsfJYZi/Ru2NzBNfXOe72fMggoU");
+        assert_eq!(key.randomart()?, concat!(
+            "+---[RSA 2048]----+\n",
+            "|                 |\n",
+            "|                 |\n",
+            "|                 |\n",
+            "|     o . o      o|\n",
+            "|      * E +  o +o|\n",
+            "|     . + * .o = =|\n",
+            "|        o .ooX.B+|\n",
+            "|         .+.+o&=*|\n",
+            "|         +=+.=+=*|\n",
+            "+----[SHA256]-----+\n",
+        ));
+        println!("{}\n{}", key.fingerprint_hex()?, key.randomart()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use num_bigint::{BigInt, Sign};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use crate::rsa::keys::{CrtPrime, Key, KeyCrt, KeyError, KeyUsage};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE};
+
+const KTY_RSA: &str = "RSA";
+/// `use` of keys that encrypt, RFC 7517 section 4.2
+pub const JWK_USE_ENC: &str = "enc";
+
+fn jwk_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("JWK: {}", msg))
+}
+
+/// `Base64urlUInt` of RFC 7518 section 2
+fn encode_uint(value: &BigInt) -> Value {
+    Value::from(base64::encode_config(value.to_bytes_be().1, base64::URL_SAFE_NO_PAD))
+}
+
+fn decode_uint(jwk: &Map<String, Value>, name: &str) -> Result<Option<BigInt>, KeyError> {
+    match jwk.get(name) {
+        None => Ok(None),
+        Some(Value::String(s)) => base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .map(|bytes| Some(BigInt::from_bytes_be(Sign::Plus, &bytes)))
+            .map_err(|_| jwk_error(&format!("`{}' is not base64url", name))),
+        Some(_) => Err(jwk_error(&format!("`{}' is not a string", name))),
+    }
+}
+
+fn require_uint(jwk: &Map<String, Value>, name: &str) -> Result<BigInt, KeyError> {
+    decode_uint(jwk, name)?.ok_or_else(|| jwk_error(&format!("missing `{}'", name)))
+}
+
+/// Whether `content` looks like a JSON document rather than a key file
+pub fn is_json(content: &[u8]) -> bool {
+    content.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{')
+}
+
+impl KeyData {
+    /// JWK thumbprint of RFC 7638, SHA-256 over the required public members in base64url
+    pub fn jwk_thumbprint(&self) -> Result<String, KeyError> {
+        let (e, n) = self.public_numbers()?;
+        // Members in lexicographic order without whitespace, as the RFC requires
+        let canonical = format!("{{\"e\":{},\"kty\":\"{}\",\"n\":{}}}", encode_uint(e), KTY_RSA, encode_uint(n));
+        Ok(base64::encode_config(Sha256::digest(canonical.as_bytes()), base64::URL_SAFE_NO_PAD))
+    }
+
+    /// RSA JWK of RFC 7517/7518, `kid` is the thumbprint, `key_use` and `alg` are left out when empty.
+    /// Usages from the metadata go to `key_ops` in place of `use`, which should not appear together.
+    pub fn to_jwk(&self, key_use: &str, alg: &str) -> Result<Value, KeyError> {
+        let (e, n) = self.public_numbers()?;
+        let mut jwk = Map::new();
+        jwk.insert("kty".to_string(), Value::from(KTY_RSA));
+        jwk.insert("kid".to_string(), Value::from(self.jwk_thumbprint()?));
+        match &self.metadata.usages {
+            Some(usages) => { jwk.insert("key_ops".to_string(), Value::from(usages.iter().map(|u| u.to_string()).collect::<Vec<_>>())); }
+            None if !key_use.is_empty() => { jwk.insert("use".to_string(), Value::from(key_use)); }
+            None => {}
+        }
+        if !alg.is_empty() { jwk.insert("alg".to_string(), Value::from(alg)); }
+        jwk.insert("n".to_string(), encode_uint(n));
+        jwk.insert("e".to_string(), encode_uint(e));
+        if self.mode == MODE_PRIVATE {
+            jwk.insert("d".to_string(), encode_uint(&self.key.base));
+            if let Some(crt) = &self.key.crt {
+                for (name, value) in [("p", &crt.p), ("q", &crt.q), ("dp", &crt.dp), ("dq", &crt.dq), ("qi", &crt.qinv)] {
+                    jwk.insert(name.to_string(), encode_uint(value));
+                }
+                if !crt.others.is_empty() {
+                    jwk.insert("oth".to_string(), Value::Array(crt.others.iter().map(|o| {
+                        let mut other = Map::new();
+                        other.insert("r".to_string(), encode_uint(&o.r));
+                        other.insert("d".to_string(), encode_uint(&o.d));
+                        other.insert("t".to_string(), encode_uint(&o.t));
+                        Value::Object(other)
+                    }).collect()));
+                }
+            }
+        }
+        Ok(Value::Object(jwk))
+    }
+
+    /// Decode an RSA JWK, private when it has `d', operations of `key_ops` this tool does not know are dropped
+    pub fn from_jwk(jwk: &Value) -> Result<KeyData, KeyError> {
+        let mut key = KeyData::from_jwk_numbers(jwk)?;
+        key.metadata.usages = match jwk.get("key_ops") {
+            None => None,
+            Some(Value::Array(ops)) => Some(ops.iter().filter_map(|op| op.as_str().and_then(|op| KeyUsage::try_from(op).ok())).collect()),
+            Some(_) => return Err(jwk_error("`key_ops' is not an array")),
+        };
+        Ok(key)
+    }
+
+    fn from_jwk_numbers(jwk: &Value) -> Result<KeyData, KeyError> {
+        let jwk = jwk.as_object().ok_or_else(|| jwk_error("not a JSON object"))?;
+        match jwk.get("kty").and_then(|k| k.as_str()) {
+            Some(KTY_RSA) => {}
+            Some(kty) => return Err(jwk_error(&format!("unsupported key type {}, only {} is supported", kty, KTY_RSA))),
+            None => return Err(jwk_error("missing `kty'")),
+        }
+        let n = require_uint(jwk, "n")?;
+        let e = require_uint(jwk, "e")?;
+        let d = match decode_uint(jwk, "d")? {
+            Some(d) => d,
+            None => return Ok(KeyData::new_public(Key::new(e, n), "".to_string())),
+        };
+        let crt = match decode_uint(jwk, "p")? {
+            Some(p) => {
+                let others = match jwk.get("oth") {
+                    None => vec![],
+                    Some(Value::Array(others)) => others.iter().map(|other| {
+                        let other = other.as_object().ok_or_else(|| jwk_error("`oth' member is not an object"))?;
+                        Ok(CrtPrime { r: require_uint(other, "r")?, d: require_uint(other, "d")?, t: require_uint(other, "t")? })
+                    }).collect::<Result<Vec<_>, KeyError>>()?,
+                    Some(_) => return Err(jwk_error("`oth' is not an array")),
+                };
+                Some(KeyCrt {
+                    e,
+                    p,
+                    q: require_uint(jwk, "q")?,
+                    dp: require_uint(jwk, "dp")?,
+                    dq: require_uint(jwk, "dq")?,
+                    qinv: require_uint(jwk, "qi")?,
+                    others,
+                })
+            }
+            None => None,
+        };
+        if let Some(crt) = &crt { crt.check(&n)?; }
+        Ok(KeyData::new_private(Key { base: d, m: n, crt }, "".to_string()))
+    }
+
+    pub fn from_jwk_str(text: &str) -> Result<KeyData, KeyError> {
+        let value: Value = serde_json::from_str(text).map_err(|e| jwk_error(&e.to_string()))?;
+        match value.get("keys") {
+            Some(_) => KeyData::from_jwks(text)?.into_iter().next().ok_or_else(|| jwk_error("no RSA key in the set")),
+            None => KeyData::from_jwk(&value),
+        }
+    }
+
+    /// JWK Set of RFC 7517 section 5
+    pub fn to_jwks(keys: &[&KeyData], key_use: &str, alg: &str) -> Result<String, KeyError> {
+        let keys = keys.iter().map(|k| k.to_jwk(key_use, alg)).collect::<Result<Vec<_>, _>>()?;
+        let mut set = Map::new();
+        set.insert("keys".to_string(), Value::Array(keys));
+        Ok(serde_json::to_string_pretty(&Value::Object(set)).unwrap() + "\n")
+    }
+
+    /// Every RSA key of a JWK Set, keys of other types are skipped as RFC 7517 section 5 asks
+    pub fn from_jwks(text: &str) -> Result<Vec<KeyData>, KeyError> {
+        let value: Value = serde_json::from_str(text).map_err(|e| jwk_error(&e.to_string()))?;
+        let keys = value.get("keys").and_then(|k| k.as_array()).ok_or_else(|| jwk_error("missing `keys' array"))?;
+        keys.iter()
+            .filter(|k| k.get("kty").and_then(|k| k.as_str()) == Some(KTY_RSA))
+            .map(KeyData::from_jwk)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use num_traits::One;
+    use serde_json::json;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::{Key, KeyError};
+    use crate::rsa::keys::key_data::{KeyData, KeyFormat};
+    use crate::rsa::keys::key_pair::KeyPair;
+
+    #[test]
+    fn test_jwk() -> Result<(), Box<dyn Error>> {
+        // Example key of RFC 7638 section 3.1
+        let jwk = json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        });
+        let public = KeyData::from_jwk(&jwk)?;
+        assert_eq!(public.key.base, BigInt::from(65537));
+        assert_eq!(public.jwk_thumbprint()?, "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+        let key_set = CONFIG_TEST.get().generate_key_primes(3)?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "".to_string()),
+            private: KeyData::new_private(key_set.private, "".to_string()),
+        };
+        let jwk = key_pair.private.to_jwk("enc", "RSA-OAEP")?;
+        println!("{}", serde_json::to_string_pretty(&jwk)?);
+        assert_eq!(jwk["kid"], key_pair.public.to_jwk("", "")?["kid"]);
+        let private = KeyData::from_jwk(&jwk)?;
+        assert_eq!(private.key.crt, key_pair.private.key.crt);
+        let crt = private.key.crt.as_ref().unwrap();
+        for (e, p, q) in [(crt.e.clone(), BigInt::one(), private.key.m.clone()), (crt.e.clone(), crt.p.clone(), &crt.q + 2), (BigInt::one(), crt.p.clone(), crt.q.clone())] {
+            let mut crafted = KeyData::new_private(private.key.clone(), "".to_string());
+            let crafted_crt = crafted.key.crt.as_mut().unwrap();
+            (crafted_crt.e, crafted_crt.p, crafted_crt.q) = (e, p, q);
+            assert!(matches!(KeyData::from_jwk(&crafted.to_jwk("enc", "")?), Err(KeyError::InvalidKey(_))));
+        }
+        key_pair.save_format("data/test_jwk".to_string(), &KeyFormat::Jwk, true)?;
+        let loaded = KeyPair::try_from("data/test_jwk".to_string())?;
+        assert_eq!(loaded.public, key_pair.public);
+        assert!(loaded.check(10).passed());
+        let other = KeyData::new_public(Key::new(BigInt::from(3), BigInt::from(187)), "".to_string());
+        let jwks = KeyData::to_jwks(&[&key_pair.public, &other], "sig", "")?;
+        let text = jwks.replace("\"keys\": [", "\"keys\": [{\"kty\": \"OKP\", \"crv\": \"Ed25519\", \"x\": \"\"},");
+        let keys = KeyData::from_jwks(&text)?;
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[1], other);
+        assert!(KeyData::from_jwk(&json!({"kty": "EC"})).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt::{Display, Formatter};
+use num::Integer;
+use num_bigint::BigInt;
+use num_traits::One;
+use crate::rsa::keys::{Key, KeyCrt};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE};
+use crate::rsa::keys::key_pair::KeyPair;
+use crate::RSA;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct KeyReport {
+    pub checks: Vec<KeyCheck>,
+}
+
+impl KeyCheck {
+    fn new(name: &'static str, passed: bool, detail: String) -> Self {
+        Self { name, status: if passed { CheckStatus::Pass } else { CheckStatus::Fail }, detail }
+    }
+
+    fn skip(name: &'static str, detail: &str) -> Self {
+        Self { name, status: CheckStatus::Skip, detail: detail.to_string() }
+    }
+}
+
+impl Display for KeyCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = match self.status {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        };
+        write!(f, "[{}] {}: {}", status, self.name, self.detail)
+    }
+}
+
+impl KeyReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&KeyCheck> {
+        self.checks.iter().find(|c| c.name == name)
+    }
+
+    pub fn print(&self) {
+        for check in &self.checks { println!("{}", check); }
+        println!("{}", if self.passed() { "Key check pass" } else { "Key check failed" });
+    }
+}
+
+impl KeyCrt {
+    fn check_params(&self, d: &BigInt) -> Result<(), String> {
+        let one = BigInt::one();
+        if self.dp != d % (&self.p - &one) { return Err("dP != d mod (p - 1)".to_string()); }
+        if self.dq != d % (&self.q - &one) { return Err("dQ != d mod (q - 1)".to_string()); }
+        if (&self.q * &self.qinv) % &self.p != one { return Err("q * qInv != 1 mod p".to_string()); }
+        let mut r_product = &self.p * &self.q;
+        for (i, other) in self.others.iter().enumerate() {
+            if other.d != d % (&other.r - &one) { return Err(format!("d_{} != d mod (r_{} - 1)", i + 3, i + 3)); }
+            if (&r_product * &other.t) % &other.r != one { return Err(format!("R_{} * t_{} != 1 mod r_{}", i + 3, i + 3, i + 3)); }
+            r_product *= &other.r;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that only need the private key
+fn check_private(key: &Key, rounds: u32, checks: &mut Vec<KeyCheck>) {
+    let n = &key.m;
+    let d = &key.base;
+    match &key.crt {
+        Some(crt) => {
+            let primes = crt.primes();
+            let product = primes.iter().product::<BigInt>();
+            checks.push(KeyCheck::new("modulus", &product == n, format!("n is the product of {} primes", primes.len())));
+            let composite = primes.iter().enumerate()
+                .filter(|(_, p)| !RSA::miller_rabin(p, rounds).unwrap_or(false))
+                .map(|(i, _)| format!("prime {}", i + 1))
+                .collect::<Vec<_>>();
+            checks.push(KeyCheck::new("primes", composite.is_empty(), match composite.is_empty() {
+                true => format!("all primes pass {} Miller-Rabin rounds", rounds),
+                false => format!("{} not prime", composite.join(", ")),
+            }));
+            let lambda = primes.iter().fold(BigInt::one(), |l, p| l.lcm(&(p - BigInt::one())));
+            checks.push(KeyCheck::new("exponents", (&crt.e * d) % &lambda == BigInt::one(), "e * d = 1 mod lcm(p_i - 1)".to_string()));
+            checks.push(match crt.check_params(d) {
+                Ok(_) => KeyCheck::new("crt", true, "dP, dQ, qInv and other primes are consistent".to_string()),
+                Err(e) => KeyCheck::new("crt", false, e),
+            });
+        }
+        None => {
+            for name in ["modulus", "primes", "exponents", "crt"] {
+                checks.push(KeyCheck::skip(name, "private key carries no primes"));
+            }
+        }
+    }
+    // Wiener's attack recovers d when d < n^(1/4) / 3
+    checks.push(KeyCheck::new("wiener", d.pow(4u32) * 81 > *n, format!("d has {} bits, n has {} bits", d.bits(), n.bits())));
+}
+
+/// Checks for a public key alone
+fn check_public(key: &Key, checks: &mut Vec<KeyCheck>) {
+    let e = &key.base;
+    let n = &key.m;
+    checks.push(KeyCheck::new("public", e > &BigInt::one() && e < n && e.is_odd(), "1 < e < n and e is odd".to_string()));
+}
+
+impl KeyData {
+    pub fn check(&self, rounds: u32) -> KeyReport {
+        let mut report = KeyReport::default();
+        match self.mode.as_str() {
+            MODE_PRIVATE => check_private(&self.key, rounds, &mut report.checks),
+            _ => check_public(&self.key, &mut report.checks),
+        };
+        report
+    }
+}
+
+impl KeyPair {
+    pub fn check(&self, rounds: u32) -> KeyReport {
+        let mut report = self.private.check(rounds);
+        check_public(&self.public.key, &mut report.checks);
+        let (public, private) = (&self.public.key, &self.private.key);
+        let pair = if public.m != private.m {
+            KeyCheck::new("pair", false, "public and private modulus differ".to_string())
+        } else {
+            match &private.crt {
+                Some(crt) if crt.e != public.base => KeyCheck::new("pair", false, "public exponent differs from the private key".to_string()),
+                _ => {
+                    let m = BigInt::from(0x114514) % &public.m;
+                    let c = public.modular_exponent(m.clone());
+                    KeyCheck::new("pair", private.modular_exponent(c) == m, "private key decodes what public key encodes".to_string())
+                }
+            }
+        };
+        report.checks.push(pair);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::key_check::CheckStatus;
+    use crate::rsa::keys::key_data::KeyData;
+    use crate::rsa::keys::key_pair::KeyPair;
+
+    #[test]
+    fn test_check_key_pair() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "".to_string()),
+            private: KeyData::new_private(key_set.private, "".to_string()),
+        };
+        let report = key_pair.check(10);
+        report.print();
+        assert!(report.passed());
+        key_pair.private.key.crt.as_mut().unwrap().dp += 2;
+        key_pair.public.key.base = BigInt::from(3);
+        let report = key_pair.check(10);
+        report.print();
+        assert_eq!(report.get("crt").unwrap().status, CheckStatus::Fail);
+        assert_eq!(report.get("pair").unwrap().status, CheckStatus::Fail);
+        assert!(!report.passed());
+        Ok(())
+    }
+}
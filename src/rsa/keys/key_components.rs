@@ -0,0 +1,221 @@
+use num::Integer;
+use num_bigint::BigInt;
+use num_traits::{Num, One, Zero};
+use serde_json::{Map, Value};
+use crate::rsa::keys::{Key, KeyCrt, KeyError, KeySet};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE};
+use crate::rsa::keys::key_pair::KeyPair;
+use crate::RSA;
+
+/// Raw numbers of a key as typed in by a user, missing values are derived on assembly
+#[derive(Debug, Default, Clone)]
+pub struct KeyComponents {
+    pub n: Option<BigInt>,
+    pub e: Option<BigInt>,
+    pub d: Option<BigInt>,
+    pub primes: Vec<BigInt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NumberFormat {
+    Decimal,
+    Hex,
+    Json,
+}
+
+impl TryFrom<&str> for NumberFormat {
+    type Error = KeyError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "dec" => Ok(NumberFormat::Decimal),
+            "hex" => Ok(NumberFormat::Hex),
+            "json" => Ok(NumberFormat::Json),
+            _ => Err(KeyError::ParseError(format!("Unknown number format `{}', available: dec, hex, json", value)))
+        }
+    }
+}
+
+/// Parse a decimal number, or a hex number with `0x' prefix
+pub fn parse_component(value: &str) -> Result<BigInt, KeyError> {
+    let value = value.trim();
+    let res = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => BigInt::from_str_radix(hex, 16),
+        None => BigInt::from_str_radix(value, 10),
+    };
+    res.map_err(|_| KeyError::ParseError(format!("`{}' is not a decimal or 0x hex number", value)))
+}
+
+impl KeyComponents {
+    /// Validate the components and derive the rest of a key pair, from (primes, e) or (n, e, d)
+    pub fn assemble(&self, rounds: u32) -> Result<KeySet, KeyError> {
+        let e = self.e.clone().ok_or_else(|| KeyError::InvalidKey("public exponent e is required".to_string()))?;
+        if e <= BigInt::one() {
+            return Err(KeyError::InvalidKey("public exponent e must be greater than 1".to_string()));
+        }
+        if let Some(i) = self.primes.iter().position(|p| p <= &BigInt::one()) {
+            return Err(KeyError::InvalidKey(format!("prime {} is not a prime", i + 1)));
+        }
+        let mut primes = self.primes.clone();
+        if primes.len() == 1 {
+            let n = self.n.as_ref().ok_or_else(|| KeyError::InvalidKey("n is required with a single prime".to_string()))?;
+            let (q, rem) = n.div_rem(&primes[0]);
+            if !rem.is_zero() { return Err(KeyError::InvalidKey("prime does not divide n".to_string())); }
+            primes.push(q);
+        }
+        if primes.is_empty() {
+            let (n, d) = match (&self.n, &self.d) {
+                (Some(n), Some(d)) => (n, d),
+                _ => return Err(KeyError::InvalidKey("need primes and e, or n, e and d".to_string()))
+            };
+            if n <= &BigInt::from(3) || n.is_even() {
+                return Err(KeyError::InvalidKey("n must be an odd number above 3".to_string()));
+            }
+            if d <= &BigInt::one() || d >= n {
+                return Err(KeyError::InvalidKey("d must be between 1 and n".to_string()));
+            }
+            primes = RSA::recover_primes(n, &e, d, rounds)?;
+            primes.reverse();
+        }
+        for (i, p) in primes.iter().enumerate() {
+            if !RSA::miller_rabin(p, rounds).unwrap_or(false) || p <= &BigInt::one() {
+                return Err(KeyError::InvalidKey(format!("prime {} is not a prime", i + 1)));
+            }
+            if primes[..i].contains(p) {
+                return Err(KeyError::InvalidKey(format!("prime {} is repeated", i + 1)));
+            }
+        }
+        let n = primes.iter().product::<BigInt>();
+        if self.n.as_ref().is_some_and(|given| given != &n) {
+            return Err(KeyError::InvalidKey("n is not the product of the primes".to_string()));
+        }
+        let f = RSA::euler_primes(&primes);
+        if !e.gcd(&f).is_one() {
+            return Err(KeyError::InvalidKey("e is not coprime to the product of (p_i - 1)".to_string()));
+        }
+        let d = match &self.d {
+            Some(d) => {
+                let lambda = primes.iter().fold(BigInt::one(), |l, p| l.lcm(&(p - BigInt::one())));
+                if (d * &e) % &lambda != BigInt::one() {
+                    return Err(KeyError::InvalidKey("e * d != 1 mod lcm(p_i - 1)".to_string()));
+                }
+                d.clone()
+            }
+            None => RSA::mod_reverse(&e, &f),
+        };
+        let crt = KeyCrt::new(&e, &d, &primes)?;
+        Ok(KeySet { public: Key::new(e, n.clone()), private: Key { base: d, m: n, crt: Some(crt) } })
+    }
+}
+
+impl KeyPair {
+    pub fn from_components(components: &KeyComponents, comment: String, rounds: u32) -> Result<Self, KeyError> {
+        let key_set = components.assemble(rounds)?;
+        let bits = key_set.public.m.bits() as usize;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, comment.clone()),
+            private: KeyData::new_private(key_set.private, comment),
+        };
+        key_pair.public.generate_header_footer_bits(bits);
+        key_pair.private.generate_header_footer_bits(bits);
+        Ok(key_pair)
+    }
+}
+
+impl KeyData {
+    /// Every number this key knows, in `RSAPrivateKey` order
+    pub fn components(&self) -> Vec<(String, BigInt)> {
+        let key = &self.key;
+        let mut res = vec![("n".to_string(), key.m.clone())];
+        if self.mode != MODE_PRIVATE {
+            res.push(("e".to_string(), key.base.clone()));
+            return res;
+        }
+        match &key.crt {
+            Some(crt) => {
+                res.push(("e".to_string(), crt.e.clone()));
+                res.push(("d".to_string(), key.base.clone()));
+                res.push(("p".to_string(), crt.p.clone()));
+                res.push(("q".to_string(), crt.q.clone()));
+                res.push(("dp".to_string(), crt.dp.clone()));
+                res.push(("dq".to_string(), crt.dq.clone()));
+                res.push(("qinv".to_string(), crt.qinv.clone()));
+                for (i, other) in crt.others.iter().enumerate() {
+                    res.push((format!("r_{}", i + 3), other.r.clone()));
+                    res.push((format!("d_{}", i + 3), other.d.clone()));
+                    res.push((format!("t_{}", i + 3), other.t.clone()));
+                }
+            }
+            None => res.push(("d".to_string(), key.base.clone())),
+        }
+        res
+    }
+
+    pub fn export_components(&self, format: &NumberFormat) -> String {
+        let components = self.components();
+        match format {
+            NumberFormat::Json => {
+                let mut map = Map::new();
+                map.insert("mode".to_string(), Value::from(self.mode.trim_end_matches('_').to_lowercase()));
+                map.insert("comment".to_string(), Value::from(self.comment.clone()));
+                for (name, value) in components {
+                    map.insert(name, Value::from(value.to_str_radix(10)));
+                }
+                serde_json::to_string_pretty(&Value::Object(map)).unwrap() + "\n"
+            }
+            _ => components.iter().map(|(name, value)| match format {
+                NumberFormat::Hex => format!("{} = 0x{}\n", name, value.to_str_radix(16)),
+                _ => format!("{} = {}\n", name, value),
+            }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::key_components::{KeyComponents, NumberFormat, parse_component};
+    use crate::rsa::keys::key_data::KeyData;
+    use crate::rsa::keys::key_pair::KeyPair;
+    use crate::rsa::keys::KeyError;
+
+    #[test]
+    fn test_assemble() -> Result<(), Box<dyn Error>> {
+        let components = KeyComponents {
+            e: Some(parse_component("7")?),
+            primes: vec![parse_component("17")?, parse_component("0xb")?],
+            ..Default::default()
+        };
+        let key_pair = KeyPair::from_components(&components, "lab".to_string(), 10)?;
+        assert_eq!(key_pair.public.key.m, BigInt::from(187));
+        assert_eq!(key_pair.private.key.base, BigInt::from(23));
+        println!("{}", key_pair.private.export_components(&NumberFormat::Hex));
+        let keys = CONFIG_TEST.get().generate_key()?;
+        let components = KeyComponents {
+            n: Some(keys.private.m.clone()),
+            e: Some(keys.public.base.clone()),
+            d: Some(keys.private.base.clone()),
+            ..Default::default()
+        };
+        let key_pair = KeyPair::from_components(&components, "".to_string(), 10)?;
+        assert_eq!(key_pair.private.key.crt.as_ref().unwrap().e, keys.public.base);
+        assert!(key_pair.check(10).passed());
+        let components = KeyComponents { e: Some(BigInt::from(3)), primes: vec![BigInt::from(7), BigInt::from(13)], ..Default::default() };
+        assert!(components.assemble(10).is_err());
+        let components = KeyComponents { n: Some(BigInt::from(187)), e: Some(BigInt::from(7)), primes: vec![BigInt::from(0)], ..Default::default() };
+        assert!(components.assemble(10).is_err());
+        let components = KeyComponents { n: Some(BigInt::from(187)), e: Some(BigInt::from(7)), primes: vec![BigInt::from(1)], ..Default::default() };
+        assert!(components.assemble(10).is_err());
+        let components = KeyComponents { e: Some(BigInt::from(1)), primes: vec![BigInt::from(17), BigInt::from(11)], ..Default::default() };
+        assert!(components.assemble(10).is_err());
+        for (n, d) in [(1, 3), (2, 3), (3, 3), (188, 23), (187, 1), (187, 187)] {
+            let components = KeyComponents { n: Some(BigInt::from(n)), e: Some(BigInt::from(7)), d: Some(BigInt::from(d)), ..Default::default() };
+            assert!(matches!(components.assemble(10), Err(KeyError::InvalidKey(_))));
+        }
+        let private = KeyData::new_private(keys.private, "".to_string());
+        println!("{}", private.export_components(&NumberFormat::Json));
+        Ok(())
+    }
+}
@@ -1,5 +1,66 @@
+use std::fmt::{Display, Formatter};
 use crate::rsa::keys::*;
 
+pub const MODE_PUBLIC: &str = "PUBLIC_";
+pub const MODE_PRIVATE: &str = "PRIVATE";
+/// Mode tag on disk for private keys followed by a CRT parameter block
+pub const MODE_PRIVATE_CRT: &str = "PRIVCRT";
+/// Start of the headers of this project's own layout
+const NATIVE_HEADER: &str = "-----BEGIN RSA-";
+
+/// Layout of a key file, text or binary is chosen apart from it
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyFormat {
+    Native,
+    /// PKCS#1 `RSAPublicKey`/`RSAPrivateKey`, PEM or DER
+    Pkcs1,
+    /// PKCS#8 `PrivateKeyInfo` and X.509 `SubjectPublicKeyInfo`, PEM or DER
+    Pkcs8,
+    /// `ssh-rsa` line for public keys, `openssh-key-v1` for private keys
+    OpenSsh,
+    /// JSON Web Key
+    Jwk,
+    /// JSON Web Key Set holding the one key
+    Jwks,
+}
+
+impl TryFrom<&str> for KeyFormat {
+    type Error = KeyError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "native" => Ok(KeyFormat::Native),
+            "pkcs1" => Ok(KeyFormat::Pkcs1),
+            "pkcs8" => Ok(KeyFormat::Pkcs8),
+            "openssh" => Ok(KeyFormat::OpenSsh),
+            "jwk" => Ok(KeyFormat::Jwk),
+            "jwks" => Ok(KeyFormat::Jwks),
+            _ => Err(KeyError::ParseError(format!("Unknown key format `{}', available: native, pkcs1, pkcs8, openssh, jwk, jwks", value)))
+        }
+    }
+}
+
+impl Display for KeyFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            KeyFormat::Native => "native",
+            KeyFormat::Pkcs1 => "pkcs1",
+            KeyFormat::Pkcs8 => "pkcs8",
+            KeyFormat::OpenSsh => "openssh",
+            KeyFormat::Jwk => "jwk",
+            KeyFormat::Jwks => "jwks",
+        })
+    }
+}
+
+pub fn pem_header(label: &str) -> String {
+    format!("-----BEGIN {}-----", label)
+}
+
+pub fn pem_footer(label: &str) -> String {
+    format!("-----END {}-----", label)
+}
+
 #[derive(Debug)]
 pub struct KeyData {
     pub(crate) mode: String,
@@ -7,6 +68,9 @@ pub struct KeyData {
     pub(crate) key: Key,
     pub(crate) header: String,
     pub(crate) footer: String,
+    pub(crate) metadata: KeyMetadata,
+    /// Read from a binary file rather than from armored text
+    pub(crate) binary: bool,
 }
 
 impl Default for KeyData {
@@ -17,6 +81,8 @@ impl Default for KeyData {
             key: Key::default(),
             header: "".to_string(),
             footer: "".to_string(),
+            metadata: KeyMetadata::default(),
+            binary: false,
         }
     }
 }
@@ -38,27 +104,49 @@ impl KeyData {
         self.footer = format!("-----END RSA-{} {} KEY-----", bits, self.mode.to_uppercase());
     }
 
+    /// Whether the header was written in this project's own layout, not imported from PEM
+    pub fn has_native_header(&self) -> bool {
+        self.header.starts_with(NATIVE_HEADER)
+    }
+
+    /// `(e, n)` of this key, private keys need their CRT parameters for e
+    pub fn public_numbers(&self) -> Result<(&BigInt, &BigInt), KeyError> {
+        match self.mode.as_str() {
+            MODE_PRIVATE => match &self.key.crt {
+                Some(crt) => Ok((&crt.e, &self.key.m)),
+                None => Err(KeyError::InvalidKey("private key carries no public exponent, run mode `recover' first".to_string())),
+            },
+            _ => Ok((&self.key.base, &self.key.m)),
+        }
+    }
+
     pub fn new_public(key: Key, comment: String) -> Self {
         Self {
-            mode: "PUBLIC_".to_string(),
+            mode: MODE_PUBLIC.to_string(),
             comment,
             key,
             header: "".to_string(),
             footer: "".to_string(),
+            metadata: KeyMetadata::default(),
+            binary: false,
         }
     }
 
     pub fn new_private(key: Key, comment: String) -> Self {
         Self {
-            mode: "PRIVATE".to_string(),
+            mode: MODE_PRIVATE.to_string(),
             comment,
             key,
             header: "".to_string(),
             footer: "".to_string(),
+            metadata: KeyMetadata::default(),
+            binary: false,
         }
     }
 
     pub fn info(&self) {
         println!("{} key, comment: {}", self.mode, self.comment);
+        self.metadata.info();
+        self.print_fingerprint();
     }
 }
\ No newline at end of file
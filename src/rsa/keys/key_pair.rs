@@ -1,4 +1,6 @@
 use std::error::Error;
+use std::io::{Read, Write};
+use crate::rsa::keys::{KeyError, KeyMetadata, KeyUsage, no_passphrase};
 use crate::rsa::keys::key_data::*;
 
 #[derive(Debug)]
@@ -7,18 +9,73 @@ pub struct KeyPair {
     pub private: KeyData,
 }
 
-impl From<String> for KeyPair {
-    fn from(path: String) -> Self {
+impl TryFrom<String> for KeyPair {
+    type Error = KeyError;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        KeyPair::load(path)
+    }
+}
+
+impl KeyPair {
+    /// Load `path` and `path.pub`, encrypted keys fail with `KeyError::Passphrase`
+    pub fn load(path: String) -> Result<Self, KeyError> {
+        KeyPair::load_with_passphrase(path, &mut no_passphrase)
+    }
+
+    /// Load `path` and `path.pub`, asking `passphrase` only when the private key is encrypted
+    pub fn load_with_passphrase(path: String, passphrase: &mut dyn FnMut() -> Result<Vec<u8>, KeyError>) -> Result<Self, KeyError> {
+        KeyPair::load_with_format(path, passphrase).map(|(key_pair, _)| key_pair)
+    }
+
+    /// Load `path` and `path.pub` in whatever format each is in, returning the format of the private key
+    pub fn load_with_format(path: String, passphrase: &mut dyn FnMut() -> Result<Vec<u8>, KeyError>) -> Result<(Self, KeyFormat), KeyError> {
         let path_public = path.clone() + ".pub";
-        Self { public: KeyData::from(path_public), private: KeyData::from(path) }
+        let (private, format) = KeyData::load_with_format(path, passphrase)?;
+        Ok((Self { public: KeyData::load(path_public)?, private }, format))
+    }
+
+    /// Read both keys of a pair, encrypted private keys fail with `KeyError::Passphrase`
+    pub fn read_from(public: impl Read, private: impl Read) -> Result<Self, KeyError> {
+        Ok(Self { public: KeyData::read_from(public)?, private: KeyData::read_from(private)? })
     }
 }
 
 impl KeyPair {
+    /// Give both keys `metadata`, the public key keeps only encrypt and verify of its usages, the private key decrypt and sign
+    pub fn set_metadata(&mut self, metadata: KeyMetadata) {
+        let only = |allowed: [KeyUsage; 2]| KeyMetadata {
+            usages: metadata.usages.as_ref().map(|usages| usages.iter().filter(|u| allowed.contains(u)).copied().collect()),
+            ..metadata.clone()
+        };
+        self.public.metadata = only([KeyUsage::Encrypt, KeyUsage::Verify]);
+        self.private.metadata = only([KeyUsage::Decrypt, KeyUsage::Sign]);
+    }
+
     pub fn save(&mut self, path: String, base64_output: bool) -> Result<(), Box<dyn Error>> {
         let path_public = path.clone() + ".pub";
-        self.public.save(path_public, base64_output).unwrap();
-        self.private.save(path, base64_output).unwrap();
+        self.public.save(path_public, base64_output)?;
+        self.private.save(path, base64_output)?;
+        Ok(())
+    }
+
+    pub fn save_format(&mut self, path: String, format: &KeyFormat, base64_output: bool) -> Result<(), Box<dyn Error>> {
+        let path_public = path.clone() + ".pub";
+        self.public.save_format(path_public, format, base64_output)?;
+        self.private.save_format(path, format, base64_output)?;
+        Ok(())
+    }
+
+    pub fn save_encrypted(&mut self, path: String, format: &KeyFormat, base64_output: bool, passphrase: &[u8]) -> Result<(), Box<dyn Error>> {
+        let path_public = path.clone() + ".pub";
+        self.public.save_format(path_public, format, base64_output)?;
+        self.private.save_encrypted(path, format, base64_output, passphrase)?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Write both keys of the pair in `format`, see `KeyData::write_to`
+    pub fn write_to(&mut self, public: impl Write, private: impl Write, format: &KeyFormat, base64_output: bool) -> Result<(), KeyError> {
+        self.public.write_to(public, format, base64_output)?;
+        self.private.write_to(private, format, base64_output)
+    }
+}
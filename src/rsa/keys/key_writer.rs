@@ -1,56 +1,61 @@
 use std::fs::File;
 use std::io::{Cursor, Read, Write};
-use crate::rsa::keys::{BASE64_SPLIT, KeyError};
-use crate::rsa::keys::key_data::KeyData;
+use zeroize::Zeroizing;
+use crate::rsa::safe_file::SafeFile;
+use crate::rsa::keys::{BASE64_SPLIT, JWK_USE_ENC, KeyError, PBKDF2_ITERATIONS, PEM_ENCRYPTED_NATIVE, PEM_ENCRYPTED_PRIVATE, PEM_OPENSSH_PRIVATE, PEM_SPLIT};
+use crate::rsa::keys::key_data::{KeyData, KeyFormat, MODE_PRIVATE, pem_footer, pem_header};
 
-pub struct KeyWriter {
-    writer: Box<dyn Write>,
-    buffer: Vec<u8>,
+pub struct KeyWriter<'a> {
+    writer: Box<dyn Write + 'a>,
+    buffer: Zeroizing<Vec<u8>>,
     pub header: String,
     pub footer: String,
+    /// Characters per base64 line
+    pub width: usize,
 }
 
-impl From<Box<(dyn Write + 'static)>> for KeyWriter {
-    fn from(f: Box<(dyn Write + 'static)>) -> Self {
+impl<'a> From<Box<dyn Write + 'a>> for KeyWriter<'a> {
+    fn from(f: Box<dyn Write + 'a>) -> Self {
         Self::new(f)
     }
 }
 
-impl From<File> for KeyWriter {
+impl From<File> for KeyWriter<'_> {
     fn from(f: File) -> Self {
         Self::new(Box::new(f))
     }
 }
 
-impl From<Box<File>> for KeyWriter {
+impl From<Box<File>> for KeyWriter<'_> {
     fn from(f: Box<File>) -> Self {
         Self::new(f)
     }
 }
 
-impl KeyWriter {
-    pub fn new(f: Box<dyn Write>) -> Self {
+impl<'a> KeyWriter<'a> {
+    pub fn new(f: Box<dyn Write + 'a>) -> Self {
         KeyWriter {
             writer: f,
-            buffer: vec![],
+            buffer: Zeroizing::new(vec![]),
             header: "".to_string(),
             footer: "".to_string(),
+            width: BASE64_SPLIT,
         }
     }
 }
 
-impl Write for KeyWriter {
+impl Write for KeyWriter<'_> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        for b in buf { self.buffer.push(*b); }
+        self.buffer.extend_from_slice(buf);
         Ok(buf.len())
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        let mut cur = Cursor::new(&self.buffer);
-        self.writer.write_all(self.header.as_bytes()).unwrap();
-        self.writer.write_all("\n".as_bytes()).unwrap();
+        let mut cur = Cursor::new(self.buffer.as_slice());
+        self.writer.write_all(self.header.as_bytes())?;
+        self.writer.write_all("\n".as_bytes())?;
+        let mut buf = Zeroizing::new(vec![0; self.width]);
         loop {
-            let mut buf: [u8; BASE64_SPLIT] = [0; BASE64_SPLIT];
             let n = cur.read(&mut buf);
             match n {
                 Ok(0) => break,
@@ -58,58 +63,126 @@ impl Write for KeyWriter {
                 _ => {}
             }
             let n = n.unwrap();
-            self.writer.write_all(&buf[0..n]).unwrap();
-            self.writer.write_all("\n".as_bytes()).unwrap();
+            self.writer.write_all(&buf[0..n])?;
+            self.writer.write_all("\n".as_bytes())?;
         }
-        self.writer.write_all(self.footer.as_bytes()).unwrap();
+        self.writer.write_all(self.footer.as_bytes())?;
+        self.writer.write_all("\n".as_bytes())?;
         self.writer.flush()
     }
 }
 
+fn io_error(e: std::io::Error) -> KeyError {
+    KeyError::Io(e.to_string())
+}
+
+/// Write the file at `path` through `write` and move it into place once complete, private keys readable by the owner only
+fn write_file(path: &str, private: bool, write: impl FnOnce(&mut SafeFile) -> Result<(), KeyError>) -> Result<(), KeyError> {
+    let path_error = |e: std::io::Error| KeyError::Io(format!("{}: {}", path, e));
+    let mut file = SafeFile::create(path, private).map_err(path_error)?;
+    write(&mut file)?;
+    file.commit().map_err(path_error)
+}
+
+/// Write `data` in base64 between `header` and `footer` lines
+pub(crate) fn write_armored(writer: impl Write, header: String, footer: String, width: usize, data: &[u8]) -> Result<(), KeyError> {
+    let mut key_writer = KeyWriter::new(Box::new(writer));
+    key_writer.header = header;
+    key_writer.footer = footer;
+    key_writer.width = width;
+    key_writer.write_all(Zeroizing::new(base64::encode(data)).as_bytes()).map_err(io_error)?;
+    key_writer.flush().map_err(io_error)
+}
+
+fn write_bytes(mut writer: impl Write, data: &[u8]) -> Result<(), KeyError> {
+    writer.write_all(data).and_then(|_| writer.flush()).map_err(io_error)
+}
+
 impl KeyData {
+    /// Save in the native format, replacing `path` only once the key is completely written
     pub fn save(&mut self, path: String, base64_output: bool) -> Result<(), KeyError> {
-        if self.footer.is_empty() && self.header.is_empty() {
-            self.generate_header_footer();
-        }
-        let base = self.key.base.to_bytes_le().1;
-        let m = self.key.m.to_bytes_le().1;
-        let mut f: Box<dyn Write> = match base64_output {
-            true => {
-                let mut key_writer = KeyWriter::from(Box::new(File::create(path).unwrap()));
-                key_writer.header = self.header.clone();
-                key_writer.footer = self.footer.clone();
-                Box::new(base64::write::EncoderWriter::new(
-                    key_writer,
-                    base64::STANDARD))
+        write_file(&path, self.mode == MODE_PRIVATE, |f| self.write_to(f, &KeyFormat::Native, base64_output))
+    }
+
+    /// Save in `format`, as PEM when `base64_output` and as DER otherwise for the standard formats
+    pub fn save_format(&mut self, path: String, format: &KeyFormat, base64_output: bool) -> Result<(), KeyError> {
+        write_file(&path, self.mode == MODE_PRIVATE, |f| self.write_to(f, format, base64_output))
+    }
+
+    /// Save a private key under `passphrase`, in `openssh-key-v1` for OpenSSH and in `EncryptedPrivateKeyInfo` for native and PKCS#8
+    pub fn save_encrypted(&mut self, path: String, format: &KeyFormat, base64_output: bool, passphrase: &[u8]) -> Result<(), KeyError> {
+        write_file(&path, true, |f| self.write_encrypted_to(f, format, base64_output, passphrase))
+    }
+
+    /// Write the key in `format` to `writer`, as PEM when `base64_output` and as DER otherwise for the standard formats.
+    /// Native keys get their header and footer generated when missing.
+    pub fn write_to(&mut self, writer: impl Write, format: &KeyFormat, base64_output: bool) -> Result<(), KeyError> {
+        let (label, der) = match format {
+            KeyFormat::Native => {
+                if self.footer.is_empty() && self.header.is_empty() {
+                    self.generate_header_footer();
+                } else if !self.has_native_header() {
+                    self.generate_header_footer_bits(self.key.m.bits() as usize);
+                }
+                let f = self.to_native();
+                return match base64_output {
+                    true => write_armored(writer, self.header.clone(), self.footer.clone(), BASE64_SPLIT, &f),
+                    false => write_bytes(writer, &f),
+                };
             }
-            false => Box::new(File::create(path).unwrap())
+            KeyFormat::Pkcs1 => (self.pkcs1_label(), self.to_pkcs1_der()?),
+            KeyFormat::Pkcs8 => (self.pkcs8_label(), self.to_pkcs8_der()?),
+            // ssh only reads the armored form, so `base64_output' does not apply
+            KeyFormat::OpenSsh if self.mode == MODE_PRIVATE => {
+                return write_armored(writer, pem_header(PEM_OPENSSH_PRIVATE), pem_footer(PEM_OPENSSH_PRIVATE), BASE64_SPLIT, &self.to_openssh_private(None)?);
+            }
+            KeyFormat::OpenSsh => return write_bytes(writer, self.to_ssh_public()?.as_bytes()),
+            KeyFormat::Jwk => {
+                let jwk = serde_json::to_string_pretty(&self.to_jwk(JWK_USE_ENC, "")?).unwrap() + "\n";
+                return write_bytes(writer, jwk.as_bytes());
+            }
+            KeyFormat::Jwks => return write_bytes(writer, KeyData::to_jwks(&[self], JWK_USE_ENC, "")?.as_bytes()),
         };
-        let lens: [u32; 2] = [base.len() as u32, m.len() as u32];
-        f.write_all(&lens[0].to_le_bytes()).unwrap();
-        f.write_all(&lens[1].to_le_bytes()).unwrap();
-        f.write_all(base.as_slice()).unwrap();
-        f.write_all(m.as_slice()).unwrap();
-        let mut mode = [0 as u8; 7];
-        for (a, b) in mode.iter_mut().zip(self.mode.bytes()) {
-            *a = b;
+        match base64_output {
+            true => write_armored(writer, pem_header(label), pem_footer(label), PEM_SPLIT, &der),
+            false => write_bytes(writer, &der),
         }
-        f.write_all(&mode).unwrap();
-        f.write_all(self.comment.as_bytes()).unwrap();
-        f.flush().unwrap();
-        Ok(())
+    }
+
+    /// Write a private key under `passphrase` to `writer`, see `save_encrypted`
+    pub fn write_encrypted_to(&mut self, writer: impl Write, format: &KeyFormat, base64_output: bool, passphrase: &[u8]) -> Result<(), KeyError> {
+        let (label, der) = match format {
+            KeyFormat::OpenSsh => (PEM_OPENSSH_PRIVATE, self.to_openssh_private(Some(passphrase))?.to_vec()),
+            KeyFormat::Pkcs8 => (PEM_ENCRYPTED_PRIVATE, self.to_encrypted_pkcs8_der(passphrase, PBKDF2_ITERATIONS)?),
+            KeyFormat::Native => (PEM_ENCRYPTED_NATIVE, self.to_encrypted_native(passphrase, PBKDF2_ITERATIONS)?),
+            _ => return Err(KeyError::InvalidKey(format!("{} keys cannot be encrypted, use native, pkcs8 or openssh", format))),
+        };
+        match (base64_output, format) {
+            (_, KeyFormat::OpenSsh) => write_armored(writer, pem_header(label), pem_footer(label), BASE64_SPLIT, &der),
+            (true, KeyFormat::Native) => write_armored(writer, pem_header(label), pem_footer(label), BASE64_SPLIT, &der),
+            (true, _) => write_armored(writer, pem_header(label), pem_footer(label), PEM_SPLIT, &der),
+            (false, _) => write_bytes(writer, &der),
+        }
+    }
+
+    /// The key as text in `format`, PEM for the standard formats
+    pub fn to_pem_string(&mut self, format: &KeyFormat) -> Result<String, KeyError> {
+        let mut data = vec![];
+        self.write_to(&mut data, format, true)?;
+        Ok(String::from_utf8(data).expect("armored keys are ASCII"))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::error::Error;
-    use crate::rsa::config::CONFIG_DEF;
+    use crate::rsa::config::CONFIG_TEST;
     use crate::rsa::keys::key_data::KeyData;
     use crate::rsa::keys::key_pair::KeyPair;
 
     #[test]
     fn key_pair_save_test() -> Result<(), Box<dyn Error>> {
-        let rsa = CONFIG_DEF.get().copy();
+        let rsa = CONFIG_TEST.get().copy();
         let key_set = rsa.generate_key().unwrap();
         let mut key_pair = KeyPair {
             public: KeyData::new_public(key_set.public, "Hello RSA!".to_string()),
@@ -0,0 +1,152 @@
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use crate::rsa::keys::KeyError;
+
+/// What a key may be used for, the names are the `key_ops` values of RFC 7517 section 4.3
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyUsage {
+    Encrypt,
+    Decrypt,
+    Sign,
+    Verify,
+}
+
+impl KeyUsage {
+    pub const ALL: [KeyUsage; 4] = [KeyUsage::Encrypt, KeyUsage::Decrypt, KeyUsage::Sign, KeyUsage::Verify];
+
+    /// Bit of this usage in the native `usages` field
+    fn bit(&self) -> u8 {
+        1 << KeyUsage::ALL.iter().position(|u| u == self).unwrap()
+    }
+}
+
+impl TryFrom<&str> for KeyUsage {
+    type Error = KeyError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        KeyUsage::ALL.into_iter().find(|u| u.to_string() == value)
+            .ok_or_else(|| KeyError::ParseError(format!("Unknown key usage `{}', available: encrypt, decrypt, sign, verify", value)))
+    }
+}
+
+impl Display for KeyUsage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            KeyUsage::Encrypt => "encrypt",
+            KeyUsage::Decrypt => "decrypt",
+            KeyUsage::Sign => "sign",
+            KeyUsage::Verify => "verify",
+        })
+    }
+}
+
+/// Comma separated usages, e.g. `encrypt,verify`
+pub fn parse_usages(value: &str) -> Result<Vec<KeyUsage>, KeyError> {
+    value.split(',').map(|u| u.trim()).filter(|u| !u.is_empty()).map(KeyUsage::try_from).collect()
+}
+
+/// Expiry as a date `2030-01-31`, an RFC 3339 time or a count of days from `now` like `90d`
+pub fn parse_expiry(value: &str, now: i64) -> Result<i64, KeyError> {
+    let error = || KeyError::ParseError(format!("Bad expiry `{}', use YYYY-MM-DD, an RFC 3339 time or days like 90d", value));
+    if let Some(days) = value.strip_suffix('d') {
+        let days = days.parse::<i64>().ok().filter(|days| *days >= 0).ok_or_else(error)?;
+        return days.checked_mul(Duration::days(1).num_seconds()).and_then(|seconds| now.checked_add(seconds)).ok_or_else(error);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().timestamp());
+    }
+    DateTime::parse_from_rfc3339(value).map(|t| t.timestamp()).map_err(|_| error())
+}
+
+fn format_time(time: i64) -> String {
+    NaiveDateTime::from_timestamp_opt(time, 0).map(|t| DateTime::<Utc>::from_utc(t, Utc).to_rfc3339()).unwrap_or_else(|| time.to_string())
+}
+
+/// What a key file records about its key besides the comment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyMetadata {
+    /// Unix time in seconds
+    pub created: Option<i64>,
+    pub expires: Option<i64>,
+    pub owner: String,
+    /// `None` for keys saved without usages, which may be used for anything
+    pub usages: Option<Vec<KeyUsage>>,
+}
+
+impl KeyMetadata {
+    pub fn usages_to_bits(&self) -> Option<u8> {
+        self.usages.as_ref().map(|usages| usages.iter().fold(0, |bits, u| bits | u.bit()))
+    }
+
+    pub fn usages_from_bits(bits: u8) -> Vec<KeyUsage> {
+        KeyUsage::ALL.into_iter().filter(|u| bits & u.bit() != 0).collect()
+    }
+
+    pub fn allows(&self, usage: KeyUsage) -> bool {
+        self.usages.as_ref().is_none_or(|usages| usages.contains(&usage))
+    }
+
+    /// Whether the key may be used for `usage` at unix time `now`
+    pub fn check(&self, usage: KeyUsage, now: i64) -> Result<(), KeyError> {
+        if let Some(expires) = self.expires {
+            if expires <= now { return Err(KeyError::Expired(format_time(expires))); }
+        }
+        match self.allows(usage) {
+            true => Ok(()),
+            false => Err(KeyError::UsageNotAllowed(usage.to_string())),
+        }
+    }
+
+    pub fn info(&self) {
+        if !self.owner.is_empty() { println!("owner: {}", self.owner); }
+        if let Some(created) = self.created { println!("created: {}", format_time(created)); }
+        if let Some(expires) = self.expires { println!("expires: {}", format_time(expires)); }
+        if let Some(usages) = &self.usages {
+            println!("usages: {}", usages.iter().map(|u| u.to_string()).collect::<Vec<_>>().join(","));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::KeyError;
+    use crate::rsa::keys::key_data::{KeyData, KeyFormat};
+    use crate::rsa::keys::key_pair::KeyPair;
+    use crate::rsa::keys::metadata::{KeyMetadata, KeyUsage, parse_expiry, parse_usages};
+
+    #[test]
+    fn test_metadata() -> Result<(), Box<dyn Error>> {
+        let now = 1_700_000_000;
+        assert_eq!(parse_expiry("2030-01-31", now)?, 1_896_048_000);
+        assert_eq!(parse_expiry("2030-01-31T01:00:00+01:00", now)?, 1_896_048_000);
+        assert_eq!(parse_expiry("2d", now)?, now + 2 * 86400);
+        assert!(parse_expiry("soon", now).is_err());
+        assert!(parse_expiry("-2d", now).is_err());
+        assert!(parse_expiry("999999999999999d", now).is_err());
+        assert_eq!(parse_usages("encrypt, verify")?, vec![KeyUsage::Encrypt, KeyUsage::Verify]);
+        assert!(parse_usages("encrypt,wrap").is_err());
+        let metadata = KeyMetadata { created: Some(now), expires: Some(now + 10), owner: "alice@example.com".to_string(), usages: Some(vec![KeyUsage::Encrypt]) };
+        assert!(metadata.check(KeyUsage::Encrypt, now).is_ok());
+        assert!(matches!(metadata.check(KeyUsage::Decrypt, now), Err(KeyError::UsageNotAllowed(_))));
+        assert!(matches!(metadata.check(KeyUsage::Encrypt, now + 10), Err(KeyError::Expired(_))));
+        assert!(KeyMetadata::default().check(KeyUsage::Sign, now).is_ok());
+
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "".to_string()),
+            private: KeyData::new_private(key_set.private, "".to_string()),
+        };
+        key_pair.public.metadata = metadata.clone();
+        key_pair.private.metadata = KeyMetadata { usages: Some(vec![KeyUsage::Decrypt, KeyUsage::Sign]), ..metadata.clone() };
+        key_pair.save("data/test_metadata".to_string(), true)?;
+        let loaded = KeyPair::load("data/test_metadata".to_string())?;
+        assert_eq!(loaded.public.metadata, key_pair.public.metadata);
+        assert_eq!(loaded.private.metadata, key_pair.private.metadata);
+        key_pair.save_format("data/test_metadata_jwk".to_string(), &KeyFormat::Jwk, true)?;
+        let loaded = KeyPair::load("data/test_metadata_jwk".to_string())?;
+        assert_eq!(loaded.private.metadata.usages, key_pair.private.metadata.usages);
+        Ok(())
+    }
+}
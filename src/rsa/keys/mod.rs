@@ -2,24 +2,253 @@ pub mod key_writer;
 pub mod key_reader;
 pub mod key_data;
 pub mod key_pair;
+pub mod key_check;
+pub mod key_components;
+pub mod pkcs1;
+pub mod pkcs8;
+pub mod openssh;
+pub mod jwk;
+pub mod pbes2;
+pub mod fingerprint;
+pub mod native;
+pub mod metadata;
+pub mod pkcs12;
+pub mod paper;
 
 pub use key_pair::*;
 pub use key_reader::*;
 pub use key_writer::*;
 pub use key_data::*;
+pub use key_check::*;
+pub use key_components::*;
+pub use pkcs1::*;
+pub use pkcs8::*;
+pub use openssh::*;
+pub use jwk::*;
+pub use pbes2::*;
+pub use native::*;
+pub use metadata::*;
+pub use pkcs12::*;
+pub use paper::*;
 
-use num_bigint::BigInt;
-use num_traits::Zero;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use num_bigint::{BigInt, Sign};
+use num_traits::{One, Zero};
+use crate::RSA;
 
-#[derive(Debug, Clone)]
+/// Overwrite the digits of `n` with zeros in place before it is freed.
+/// num-bigint cannot wipe its own temporaries, so this only covers values we hold.
+pub(crate) fn wipe(n: &mut BigInt) {
+    // `assign_from_slice` refills the same buffer when it is large enough, one u64 digit is two u32 words
+    let zeros = vec![0_u32; n.bits().div_ceil(64) as usize * 2];
+    n.assign_from_slice(Sign::Plus, &zeros);
+    std::hint::black_box(&*n);
+}
+
+/// Check that `primes` are at least two numbers above 1 whose product is `n`, before anything divides by them
+pub(crate) fn check_primes(n: &BigInt, primes: &[&BigInt]) -> Result<(), KeyError> {
+    if primes.len() < 2 {
+        return Err(KeyError::InvalidKey(format!("RSA needs at least 2 primes, got {}", primes.len())));
+    }
+    if let Some(i) = primes.iter().position(|p| **p <= BigInt::one()) {
+        return Err(KeyError::InvalidKey(format!("prime {} is below 2", i + 1)));
+    }
+    if primes.iter().copied().product::<BigInt>() != *n {
+        return Err(KeyError::InvalidKey("n is not the product of the primes".to_string()));
+    }
+    Ok(())
+}
+
+/// Stands in for secret values in `Debug` output
+struct Redacted;
+
+impl Debug for Redacted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// `base` is the private exponent of private keys, so it is wiped on drop and never printed
+#[derive(Clone)]
 pub struct Key {
     pub base: BigInt,
     pub m: BigInt,
+    /// CRT parameters, only present on private keys that know their primes
+    pub crt: Option<KeyCrt>,
+}
+
+/// Private key parameters for CRT decryption, named as `RSAPrivateKey` in RFC 8017
+#[derive(Clone, PartialEq)]
+pub struct KeyCrt {
+    pub e: BigInt,
+    pub p: BigInt,
+    pub q: BigInt,
+    pub dp: BigInt,
+    pub dq: BigInt,
+    pub qinv: BigInt,
+    /// Primes after `p` and `q` in a multi-prime key
+    pub others: Vec<CrtPrime>,
+}
+
+/// `OtherPrimeInfo` in RFC 8017
+#[derive(Clone, PartialEq)]
+pub struct CrtPrime {
+    pub r: BigInt,
+    pub d: BigInt,
+    pub t: BigInt,
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key").field("base", &Redacted).field("m", &self.m).field("crt", &self.crt).finish()
+    }
+}
+
+impl Debug for KeyCrt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyCrt").field("e", &self.e).field("primes", &(2 + self.others.len())).finish_non_exhaustive()
+    }
+}
+
+impl Debug for CrtPrime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CrtPrime").finish_non_exhaustive()
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        wipe(&mut self.base);
+    }
+}
+
+impl Drop for KeyCrt {
+    fn drop(&mut self) {
+        for value in [&mut self.p, &mut self.q, &mut self.dp, &mut self.dq, &mut self.qinv] {
+            wipe(value);
+        }
+    }
+}
+
+impl Drop for CrtPrime {
+    fn drop(&mut self) {
+        for value in [&mut self.r, &mut self.d, &mut self.t] {
+            wipe(value);
+        }
+    }
 }
 
 impl Default for Key {
     fn default() -> Self {
-        Self { base: BigInt::zero(), m: BigInt::zero() }
+        Self { base: BigInt::zero(), m: BigInt::zero(), crt: None }
+    }
+}
+
+impl Key {
+    pub fn new(base: BigInt, m: BigInt) -> Self {
+        Self { base, m, crt: None }
+    }
+
+    /// Calculate `data ^ base mod m`, using CRT when the primes are known
+    pub fn modular_exponent(&self, data: BigInt) -> BigInt {
+        match &self.crt {
+            Some(crt) => crt.modular_exponent(data),
+            None => data.modpow(&self.base, &self.m)
+        }
+    }
+}
+
+impl KeyCrt {
+    /// Build CRT parameters from at least two distinct primes
+    pub fn new(e: &BigInt, d: &BigInt, primes: &[BigInt]) -> Result<Self, KeyError> {
+        let (p, q) = match primes {
+            [p, q, ..] => (p, q),
+            _ => return Err(KeyError::InvalidKey(format!("RSA needs at least 2 primes, got {}", primes.len()))),
+        };
+        if let Some(i) = primes.iter().position(|p| *p <= BigInt::one()) {
+            return Err(KeyError::InvalidKey(format!("prime {} is below 2", i + 1)));
+        }
+        let mut r_product = p * q;
+        let others = primes[2..].iter().map(|r| {
+            let other = CrtPrime {
+                r: r.clone(),
+                d: d % (r - BigInt::one()),
+                t: RSA::mod_reverse(&r_product, r),
+            };
+            r_product *= r;
+            other
+        }).collect();
+        Ok(Self {
+            e: e.clone(),
+            p: p.clone(),
+            q: q.clone(),
+            dp: d % (p - BigInt::one()),
+            dq: d % (q - BigInt::one()),
+            qinv: RSA::mod_reverse(q, p),
+            others,
+        })
+    }
+
+    pub fn primes(&self) -> Vec<BigInt> {
+        let mut primes = vec![self.p.clone(), self.q.clone()];
+        primes.extend(self.others.iter().map(|o| o.r.clone()));
+        primes
+    }
+
+    pub fn to_vec(&self) -> Vec<BigInt> {
+        let mut values = vec![self.e.clone(), self.p.clone(), self.q.clone(), self.dp.clone(), self.dq.clone(), self.qinv.clone()];
+        for other in &self.others {
+            values.extend([other.r.clone(), other.d.clone(), other.t.clone()]);
+        }
+        values
+    }
+
+    /// Check CRT values read from a file against the modulus `n`, so nothing divides by a bad prime later
+    pub(crate) fn check(&self, n: &BigInt) -> Result<(), KeyError> {
+        if self.e <= BigInt::one() {
+            return Err(KeyError::InvalidKey("public exponent must be above 1".to_string()));
+        }
+        if [&self.dp, &self.dq].into_iter().chain(self.others.iter().map(|o| &o.d)).any(|d| d.sign() == Sign::Minus) {
+            return Err(KeyError::InvalidKey("negative CRT exponent".to_string()));
+        }
+        let primes = [&self.p, &self.q].into_iter().chain(self.others.iter().map(|o| &o.r)).collect::<Vec<_>>();
+        check_primes(n, &primes)
+    }
+
+    pub fn from_vec(values: &[BigInt]) -> Result<Self, KeyError> {
+        if values.len() < 6 || !(values.len() - 6).is_multiple_of(3) {
+            return Err(KeyError::ParseError(format!("Expect 6 + 3 * k CRT values, got {}", values.len())));
+        }
+        Ok(Self {
+            e: values[0].clone(),
+            p: values[1].clone(),
+            q: values[2].clone(),
+            dp: values[3].clone(),
+            dq: values[4].clone(),
+            qinv: values[5].clone(),
+            others: values[6..].chunks(3).map(|v| CrtPrime {
+                r: v[0].clone(),
+                d: v[1].clone(),
+                t: v[2].clone(),
+            }).collect(),
+        })
+    }
+
+    /// RSADP with the CRT method, RFC 8017 section 5.1.2
+    pub fn modular_exponent(&self, data: BigInt) -> BigInt {
+        let m1 = (&data % &self.p).modpow(&self.dp, &self.p);
+        let m2 = (&data % &self.q).modpow(&self.dq, &self.q);
+        let h = (((&m1 - &m2) % &self.p + &self.p) * &self.qinv) % &self.p;
+        let mut m = m2 + h * &self.q;
+        let mut r_product = &self.p * &self.q;
+        for other in &self.others {
+            let mi = (&data % &other.r).modpow(&other.d, &other.r);
+            let h = (((mi - &m) % &other.r + &other.r) * &other.t) % &other.r;
+            m += &r_product * h;
+            r_product *= &other.r;
+        }
+        m
     }
 }
 
@@ -35,10 +264,92 @@ pub struct KeySet {
     pub private: Key,
 }
 
-#[derive(Debug)]
 pub enum KeyError {
     ParseError(String),
     FormatError,
+    InvalidKey(String),
+    Passphrase(String),
+    /// The key file at this path does not exist
+    NotFound(String),
+    Truncated,
+    BadBase64(String),
+    /// Labels of the BEGIN and END lines differ
+    HeaderMismatch(String, String),
+    UnknownMode(String),
+    BadUtf8Comment,
+    /// A file of this many keys was read as a single key
+    MultipleKeys(usize),
+    /// The key expired at this time
+    Expired(String),
+    /// The key's usages do not include this operation
+    UsageNotAllowed(String),
+    /// Reading or writing key data failed
+    Io(String),
+    /// This line of a paper backup is wrong, numbered from 1
+    BadLine(usize, String),
+}
+
+impl KeyError {
+    fn display(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::ParseError(msg) => write!(f, "Key parse error: {}", msg),
+            KeyError::FormatError => write!(f, "Key format error"),
+            KeyError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            KeyError::Passphrase(msg) => write!(f, "Passphrase error: {}", msg),
+            KeyError::NotFound(path) => write!(f, "Key file not found: {}", path),
+            KeyError::Truncated => write!(f, "Key data is truncated"),
+            KeyError::BadBase64(msg) => write!(f, "Bad base64 in key file: {}", msg),
+            KeyError::HeaderMismatch(header, footer) => write!(f, "Key header `{}' does not match footer `{}'", header, footer),
+            KeyError::UnknownMode(mode) => write!(f, "Unknown key mode `{}'", mode),
+            KeyError::BadUtf8Comment => write!(f, "Key comment is not valid UTF-8"),
+            KeyError::MultipleKeys(n) => write!(f, "Key file holds {} keys, load it as a bundle", n),
+            KeyError::Expired(time) => write!(f, "Key expired at {}", time),
+            KeyError::UsageNotAllowed(usage) => write!(f, "Key is not marked for {}", usage),
+            KeyError::Io(msg) => write!(f, "Key I/O error: {}", msg),
+            KeyError::BadLine(line, msg) => write!(f, "Paper backup line {}: {}", line, msg),
+        }
+    }
+}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.display(f)
+    }
 }
 
+impl Debug for KeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.display(f)
+    }
+}
+
+impl Error for KeyError {}
+
 const BASE64_SPLIT: usize = 70;
+/// Line width of PEM, RFC 7468
+const PEM_SPLIT: usize = 64;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use num_traits::Zero;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::wipe;
+
+    #[test]
+    fn test_secrets() -> Result<(), Box<dyn Error>> {
+        let mut n = BigInt::from(u128::MAX) * BigInt::from(u128::MAX);
+        wipe(&mut n);
+        assert!(n.is_zero());
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let crt = key_set.private.crt.as_ref().unwrap();
+        for debug in [format!("{:?}", key_set), format!("{:?}", crt)] {
+            for secret in [&key_set.private.base, &crt.p, &crt.q, &crt.dp] {
+                assert!(!debug.contains(&secret.to_string()), "{} shows a secret", debug);
+            }
+        }
+        assert!(format!("{:?}", key_set).contains(&key_set.public.m.to_string()));
+        Ok(())
+    }
+}
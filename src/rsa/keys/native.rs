@@ -0,0 +1,302 @@
+use std::cmp::Ordering;
+use num_bigint::{BigInt, Sign};
+use zeroize::Zeroizing;
+use crate::rsa::keys::{Key, KeyCrt, KeyError, KeyMetadata};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE, MODE_PRIVATE_CRT, MODE_PUBLIC};
+
+/// Start of every native key since version 2, older files begin with the length of the exponent.
+/// Like PNG's signature the high byte keeps it binary and the line endings catch text mode transfers.
+pub const NATIVE_MAGIC: &[u8; 8] = b"\x89RSA\r\n\x1a\n";
+pub const NATIVE_VERSION: u16 = 2;
+/// Version of the files without magic, as written before version 2
+pub const NATIVE_LEGACY_VERSION: u16 = 1;
+
+/// Bytes of magic, `version: u16` and `length: u32` of the fields
+const HEADER_BYTES: usize = NATIVE_MAGIC.len() + 2 + 4;
+const CHECKSUM_BYTES: usize = 4;
+
+/// Fields of version 2, each `tag: u8`, `length: u32` and the value, all little-endian.
+/// Unknown tags are skipped so that later versions can add fields.
+const FIELD_MODE: u8 = 1;
+const FIELD_EXPONENT: u8 = 2;
+const FIELD_MODULUS: u8 = 3;
+/// Repeated once per value of `KeyCrt::to_vec`, in order
+const FIELD_CRT: u8 = 4;
+const FIELD_COMMENT: u8 = 5;
+/// Unix seconds as i64
+const FIELD_CREATED: u8 = 6;
+const FIELD_EXPIRES: u8 = 7;
+const FIELD_OWNER: u8 = 8;
+/// One bit per `KeyUsage`, absent when the key may be used for anything
+const FIELD_USAGES: u8 = 9;
+
+const MODE_BYTE_PUBLIC: u8 = 0;
+const MODE_BYTE_PRIVATE: u8 = 1;
+const LEGACY_MODE_BYTES: usize = 7;
+
+fn native_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("native key: {}", msg))
+}
+
+struct NativeReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> NativeReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], KeyError> {
+        if self.data.len() < len { return Err(KeyError::Truncated); }
+        let (res, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, KeyError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, KeyError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn i64(&mut self, len: usize) -> Result<i64, KeyError> {
+        let bytes: [u8; 8] = self.bytes(len)?.try_into().map_err(|_| native_error("bad time field"))?;
+        Ok(i64::from_le_bytes(bytes))
+    }
+
+    fn bigint(&mut self, len: usize) -> Result<BigInt, KeyError> {
+        Ok(BigInt::from_bytes_le(Sign::Plus, self.bytes(len)?))
+    }
+}
+
+fn write_field(data: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    data.push(tag);
+    data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    data.extend_from_slice(value);
+}
+
+fn comment_from_bytes(bytes: &[u8]) -> Result<String, KeyError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| KeyError::BadUtf8Comment)
+}
+
+/// Version of the native layout `data` is in, legacy files have none to tell
+pub fn native_version(data: &[u8]) -> u16 {
+    match data.strip_prefix(NATIVE_MAGIC.as_slice()) {
+        Some([low, high, ..]) => u16::from_le_bytes([*low, *high]),
+        _ => NATIVE_LEGACY_VERSION,
+    }
+}
+
+impl KeyData {
+    /// Native layout version 2: magic, version, length, tagged fields and a CRC-32 of everything before it.
+    /// Private keys hold d and the CRT values here, so the buffer is wiped when dropped.
+    pub fn to_native(&self) -> Zeroizing<Vec<u8>> {
+        // Sized up front so that growing it leaves no copies of the secrets behind
+        let numbers = 2 + self.key.crt.as_ref().map_or(0, |crt| 6 + 3 * crt.others.len());
+        let capacity = numbers * (self.key.m.bits() as usize / 8 + 8) + self.comment.len() + self.metadata.owner.len() + 64;
+        let mut data = Zeroizing::new(Vec::with_capacity(capacity));
+        let mode = match self.mode.as_str() {
+            MODE_PRIVATE => MODE_BYTE_PRIVATE,
+            _ => MODE_BYTE_PUBLIC,
+        };
+        write_field(&mut data, FIELD_MODE, &[mode]);
+        write_field(&mut data, FIELD_EXPONENT, &Zeroizing::new(self.key.base.to_bytes_le().1));
+        write_field(&mut data, FIELD_MODULUS, &self.key.m.to_bytes_le().1);
+        if let (MODE_BYTE_PRIVATE, Some(crt)) = (mode, &self.key.crt) {
+            for value in crt.to_vec() {
+                write_field(&mut data, FIELD_CRT, &Zeroizing::new(value.to_bytes_le().1));
+            }
+        }
+        write_field(&mut data, FIELD_COMMENT, self.comment.as_bytes());
+        if let Some(created) = self.metadata.created {
+            write_field(&mut data, FIELD_CREATED, &created.to_le_bytes());
+        }
+        if let Some(expires) = self.metadata.expires {
+            write_field(&mut data, FIELD_EXPIRES, &expires.to_le_bytes());
+        }
+        if !self.metadata.owner.is_empty() {
+            write_field(&mut data, FIELD_OWNER, self.metadata.owner.as_bytes());
+        }
+        if let Some(bits) = self.metadata.usages_to_bits() {
+            write_field(&mut data, FIELD_USAGES, &[bits]);
+        }
+        let fields = data;
+        let mut data = Zeroizing::new(Vec::with_capacity(NATIVE_MAGIC.len() + 10 + fields.len()));
+        data.extend_from_slice(NATIVE_MAGIC.as_slice());
+        data.extend_from_slice(&NATIVE_VERSION.to_le_bytes());
+        data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        data.extend_from_slice(&fields);
+        let checksum = crc32fast::hash(&data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    /// Decode a native key of version 2 or of the legacy layout, the header and footer are left empty
+    pub fn from_native(data: &[u8]) -> Result<KeyData, KeyError> {
+        match native_version(data) {
+            NATIVE_LEGACY_VERSION => KeyData::from_native_legacy(data),
+            NATIVE_VERSION => KeyData::from_native_v2(data),
+            version => Err(native_error(&format!("unsupported version {}, this build reads up to {}", version, NATIVE_VERSION))),
+        }
+    }
+
+    fn from_native_v2(data: &[u8]) -> Result<KeyData, KeyError> {
+        let len = NativeReader { data: data.get(NATIVE_MAGIC.len() + 2..).unwrap_or_default() }.u32()?;
+        match data.len().cmp(&(HEADER_BYTES + len + CHECKSUM_BYTES)) {
+            Ordering::Less => return Err(KeyError::Truncated),
+            Ordering::Greater => return Err(native_error("trailing data")),
+            Ordering::Equal => {}
+        }
+        let (body, checksum) = data.split_at(HEADER_BYTES + len);
+        if crc32fast::hash(body).to_le_bytes() != checksum {
+            return Err(native_error("checksum mismatch, the file is corrupt"));
+        }
+        let mut reader = NativeReader { data: &body[HEADER_BYTES..] };
+        let (mut mode, mut base, mut m, mut crt, mut comment) = (None, None, None, vec![], String::new());
+        let mut metadata = KeyMetadata::default();
+        while !reader.data.is_empty() {
+            let tag = reader.u8()?;
+            let len = reader.u32()?;
+            match tag {
+                FIELD_MODE => mode = Some(match reader.bytes(len)? {
+                    [MODE_BYTE_PUBLIC] => MODE_PUBLIC,
+                    [MODE_BYTE_PRIVATE] => MODE_PRIVATE,
+                    other => return Err(KeyError::UnknownMode(format!("{:02x?}", other))),
+                }),
+                FIELD_EXPONENT => base = Some(reader.bigint(len)?),
+                FIELD_MODULUS => m = Some(reader.bigint(len)?),
+                FIELD_CRT => crt.push(reader.bigint(len)?),
+                FIELD_COMMENT => comment = comment_from_bytes(reader.bytes(len)?)?,
+                FIELD_CREATED => metadata.created = Some(reader.i64(len)?),
+                FIELD_EXPIRES => metadata.expires = Some(reader.i64(len)?),
+                FIELD_OWNER => metadata.owner = String::from_utf8(reader.bytes(len)?.to_vec())
+                    .map_err(|_| native_error("owner is not valid UTF-8"))?,
+                FIELD_USAGES => metadata.usages = Some(match reader.bytes(len)? {
+                    [bits] => KeyMetadata::usages_from_bits(*bits),
+                    _ => return Err(native_error("bad usages field")),
+                }),
+                _ => { reader.bytes(len)?; }
+            }
+        }
+        let mode = mode.ok_or_else(|| native_error("missing key mode"))?;
+        let m = m.ok_or_else(|| native_error("missing modulus"))?;
+        let crt = match (mode, crt.is_empty()) {
+            (MODE_PRIVATE, false) => Some(KeyCrt::from_vec(&crt)?),
+            _ => None,
+        };
+        if let Some(crt) = &crt { crt.check(&m)?; }
+        Ok(KeyData {
+            mode: mode.to_string(),
+            comment,
+            key: Key {
+                base: base.ok_or_else(|| native_error("missing exponent"))?,
+                m,
+                crt,
+            },
+            header: "".to_string(),
+            footer: "".to_string(),
+            metadata,
+            binary: false,
+        })
+    }
+
+    /// Two u32 lengths, base and m, a 7 byte mode, the CRT block for `PRIVCRT` and the comment to the end
+    fn from_native_legacy(data: &[u8]) -> Result<KeyData, KeyError> {
+        let mut reader = NativeReader { data };
+        let (len_base, len_m) = (reader.u32()?, reader.u32()?);
+        let base = reader.bigint(len_base)?;
+        let m = reader.bigint(len_m)?;
+        let mode = reader.bytes(LEGACY_MODE_BYTES)?;
+        let (mode, crt) = match std::str::from_utf8(mode) {
+            Ok(MODE_PRIVATE_CRT) => {
+                let count = reader.u32()?;
+                let values = (0..count).map(|_| {
+                    let len = reader.u32()?;
+                    reader.bigint(len)
+                }).collect::<Result<Vec<_>, _>>()?;
+                let crt = KeyCrt::from_vec(&values)?;
+                crt.check(&m)?;
+                (MODE_PRIVATE, Some(crt))
+            }
+            Ok(MODE_PRIVATE) => (MODE_PRIVATE, None),
+            Ok(MODE_PUBLIC) => (MODE_PUBLIC, None),
+            _ => return Err(KeyError::UnknownMode(String::from_utf8_lossy(mode).to_string())),
+        };
+        Ok(KeyData {
+            mode: mode.to_string(),
+            comment: comment_from_bytes(reader.data)?,
+            key: Key { base, m, crt },
+            header: "".to_string(),
+            footer: "".to_string(),
+            metadata: KeyMetadata::default(),
+            binary: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use num_traits::One;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::KeyError;
+    use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE_CRT};
+    use crate::rsa::keys::native::{native_version, NATIVE_LEGACY_VERSION, NATIVE_VERSION};
+
+    /// The layout `KeyData::save` wrote before version 2
+    fn to_native_legacy(key: &KeyData) -> Vec<u8> {
+        let (base, m) = (key.key.base.to_bytes_le().1, key.key.m.to_bytes_le().1);
+        let mut data = [(base.len() as u32).to_le_bytes(), (m.len() as u32).to_le_bytes()].concat();
+        data.extend(base);
+        data.extend(m);
+        data.extend(MODE_PRIVATE_CRT.as_bytes());
+        let values = key.key.crt.as_ref().unwrap().to_vec();
+        data.extend((values.len() as u32).to_le_bytes());
+        for value in values {
+            let value = value.to_bytes_le().1;
+            data.extend((value.len() as u32).to_le_bytes());
+            data.extend(value);
+        }
+        data.extend(key.comment.as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_native() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key_primes(3)?;
+        let private = KeyData::new_private(key_set.private, "Hello RSA!".to_string());
+        let public = KeyData::new_public(key_set.public, "".to_string());
+        for key in [&private, &public] {
+            let data = key.to_native();
+            assert_eq!(native_version(&data), NATIVE_VERSION);
+            let loaded = KeyData::from_native(&data)?;
+            assert_eq!((&loaded.mode, &loaded.comment, &loaded.key.crt), (&key.mode, &key.comment, &key.key.crt));
+            assert_eq!(loaded, *key);
+            for len in 0..data.len() {
+                assert!(KeyData::from_native(&data[..len]).is_err());
+            }
+        }
+        let crt = private.key.crt.as_ref().unwrap();
+        for (e, p, q) in [(crt.e.clone(), BigInt::one(), private.key.m.clone()), (crt.e.clone(), crt.p.clone(), &crt.q + 2), (BigInt::one(), crt.p.clone(), crt.q.clone())] {
+            let mut crafted = KeyData::new_private(private.key.clone(), "".to_string());
+            let crafted_crt = crafted.key.crt.as_mut().unwrap();
+            (crafted_crt.e, crafted_crt.p, crafted_crt.q) = (e, p, q);
+            assert!(matches!(KeyData::from_native(&crafted.to_native()), Err(KeyError::InvalidKey(_))));
+            assert!(matches!(KeyData::from_native(&to_native_legacy(&crafted)), Err(KeyError::InvalidKey(_))));
+        }
+        let mut data = private.to_native();
+        data[20] ^= 1;
+        assert!(KeyData::from_native(&data).is_err());
+        let legacy = to_native_legacy(&private);
+        assert_eq!(native_version(&legacy), NATIVE_LEGACY_VERSION);
+        let loaded = KeyData::from_native(&legacy)?;
+        assert_eq!((&loaded.mode, &loaded.comment, &loaded.key.crt), (&private.mode, &private.comment, &private.key.crt));
+        // The comment runs to the end, so only cuts before it are noticed
+        let comment_start = legacy.len() - private.comment.len();
+        for len in 0..comment_start {
+            assert!(KeyData::from_native(&legacy[..len]).is_err());
+        }
+        Ok(())
+    }
+}
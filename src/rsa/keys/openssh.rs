@@ -0,0 +1,324 @@
+use aes::Aes256;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use num_bigint::{BigInt, Sign};
+use num_traits::{One, Zero};
+use rand::Rng;
+use zeroize::Zeroizing;
+use crate::rsa::keys::{Key, KeyCrt, KeyError, check_primes};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE, pem_footer, pem_header};
+
+pub const SSH_RSA: &str = "ssh-rsa";
+pub const PEM_OPENSSH_PRIVATE: &str = "OPENSSH PRIVATE KEY";
+const OPENSSH_MAGIC: &[u8] = b"openssh-key-v1\0";
+const CIPHER_NONE: &str = "none";
+const CIPHER_AES256_CTR: &str = "aes256-ctr";
+const KDF_NONE: &str = "none";
+const KDF_BCRYPT: &str = "bcrypt";
+/// Defaults of `ssh-keygen`
+const BCRYPT_SALT_BYTES: usize = 16;
+const BCRYPT_ROUNDS: u32 = 16;
+/// Most bcrypt-pbkdf rounds accepted from a file, 64 times what `ssh-keygen` writes by default
+const MAX_BCRYPT_ROUNDS: u32 = BCRYPT_ROUNDS * 64;
+/// First words of key types in `authorized_keys` and `.pub` files of OpenSSH
+const SSH_KEY_TYPE_PREFIXES: [&str; 3] = ["ssh-", "ecdsa-sha2-", "sk-"];
+
+fn ssh_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("OpenSSH: {}", msg))
+}
+
+/// Encoder for the data types of RFC 4251 section 5
+#[derive(Default)]
+pub struct SshWriter {
+    pub data: Vec<u8>,
+}
+
+impl SshWriter {
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn string(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+        self
+    }
+
+    pub fn mpint(&mut self, value: &BigInt) -> &mut Self {
+        match value.is_zero() {
+            true => self.string(&[]),
+            false => self.string(&value.to_signed_bytes_be()),
+        }
+    }
+}
+
+/// Decoder for the data types of RFC 4251 section 5
+pub struct SshReader<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> SshReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], KeyError> {
+        if self.data.len() < len { return Err(KeyError::Truncated); }
+        let (res, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(res)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, KeyError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn string(&mut self) -> Result<&'a [u8], KeyError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub fn mpint(&mut self) -> Result<BigInt, KeyError> {
+        let value = BigInt::from_signed_bytes_be(self.string()?);
+        match value.sign() {
+            Sign::Minus => Err(ssh_error("negative mpint")),
+            _ => Ok(value),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Whether `content` looks like a one-line OpenSSH public key, maybe with `authorized_keys` options in front
+pub fn is_ssh_public(content: &[u8]) -> bool {
+    let text = match std::str::from_utf8(content) {
+        Ok(text) => text,
+        Err(_) => return false,
+    };
+    text.lines().map(|l| l.trim()).find(|l| !l.is_empty() && !l.starts_with('#'))
+        .is_some_and(|l| l.split_whitespace().any(|w| SSH_KEY_TYPE_PREFIXES.iter().any(|p| w.starts_with(p))))
+}
+
+impl KeyData {
+    /// Public key blob of RFC 4253 section 6.6
+    pub fn ssh_public_blob(&self) -> Result<Vec<u8>, KeyError> {
+        let (e, n) = self.public_numbers()?;
+        let mut writer = SshWriter::default();
+        writer.string(SSH_RSA.as_bytes()).mpint(e).mpint(n);
+        Ok(writer.data)
+    }
+
+    /// `ssh-rsa AAAA... comment` as in `authorized_keys`
+    pub fn to_ssh_public(&self) -> Result<String, KeyError> {
+        let blob = base64::encode(self.ssh_public_blob()?);
+        let comment = self.comment.split_whitespace().collect::<Vec<_>>().join(" ");
+        Ok(match comment.is_empty() {
+            true => format!("{} {}\n", SSH_RSA, blob),
+            false => format!("{} {} {}\n", SSH_RSA, blob, comment),
+        })
+    }
+
+    /// Parse the first key line of a `.pub` or `authorized_keys` file, skipping any options
+    pub fn from_ssh_public(text: &str) -> Result<KeyData, KeyError> {
+        let line = text.lines().map(|l| l.trim()).find(|l| !l.is_empty() && !l.starts_with('#'))
+            .ok_or_else(|| ssh_error("no key line"))?;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let index = words.iter().position(|w| SSH_KEY_TYPE_PREFIXES.iter().any(|p| w.starts_with(p)))
+            .ok_or_else(|| ssh_error("no key type in line"))?;
+        if words[index] != SSH_RSA {
+            return Err(ssh_error(&format!("unsupported key type {}, only {} is supported", words[index], SSH_RSA)));
+        }
+        let blob = words.get(index + 1).ok_or_else(|| ssh_error("missing key data"))?;
+        let blob = base64::decode(blob).map_err(|_| ssh_error("key data is not base64"))?;
+        let mut key_data = KeyData::from_ssh_blob(&blob)?;
+        key_data.comment = words[index + 2..].join(" ");
+        Ok(key_data)
+    }
+}
+
+/// Whether an `openssh-key-v1` container is encrypted
+pub fn is_openssh_encrypted(data: &[u8]) -> bool {
+    let mut reader = SshReader::new(data);
+    reader.bytes(OPENSSH_MAGIC.len()).is_ok_and(|magic| magic == OPENSSH_MAGIC)
+        && reader.string().is_ok_and(|cipher| cipher != CIPHER_NONE.as_bytes())
+}
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// aes256-ctr with key and IV from bcrypt-pbkdf, as `ssh-keygen` does it
+fn openssh_cipher(passphrase: &[u8], salt: &[u8], rounds: u32) -> Result<Aes256Ctr, KeyError> {
+    let mut key_iv = [0u8; 48];
+    bcrypt_pbkdf::bcrypt_pbkdf(passphrase, salt, rounds, &mut key_iv)
+        .map_err(|e| ssh_error(&format!("bcrypt-pbkdf: {}", e)))?;
+    Ok(Aes256Ctr::new(key_iv[..32].into(), key_iv[32..].into()))
+}
+
+impl KeyData {
+    /// `openssh-key-v1` container of OpenSSH's PROTOCOL.key, encrypted with bcrypt-pbkdf and aes256-ctr given a passphrase
+    pub fn to_openssh_private(&self, passphrase: Option<&[u8]>) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+        if self.mode != MODE_PRIVATE { return Err(KeyError::InvalidKey("openssh-key-v1 holds private keys only".to_string())); }
+        let crt = self.key.crt.as_ref()
+            .ok_or_else(|| KeyError::InvalidKey("OpenSSH private keys need the primes, run mode `recover' first".to_string()))?;
+        if !crt.others.is_empty() { return Err(KeyError::InvalidKey("OpenSSH does not support multi-prime keys".to_string())); }
+        let public = self.ssh_public_blob()?;
+        let check = rand::thread_rng().gen::<u32>();
+        // room for n, d and the three halves up front, growing would leave copies of the key behind
+        let mut private = SshWriter { data: Vec::with_capacity(4 * self.key.m.bits() as usize / 8 + self.comment.len() + 128) };
+        private.u32(check).u32(check).string(SSH_RSA.as_bytes())
+            .mpint(&self.key.m).mpint(&crt.e).mpint(&self.key.base).mpint(&crt.qinv).mpint(&crt.p).mpint(&crt.q)
+            .string(self.comment.as_bytes());
+        let mut private = Zeroizing::new(private.data);
+        let block = if passphrase.is_some() { 16 } else { 8 };
+        let mut pad = 1;
+        while !private.len().is_multiple_of(block) {
+            private.push(pad);
+            pad += 1;
+        }
+        let mut writer = SshWriter { data: Vec::with_capacity(private.len() + public.len() + 128) };
+        writer.data.extend_from_slice(OPENSSH_MAGIC);
+        match passphrase {
+            Some(passphrase) => {
+                let salt = rand::thread_rng().gen::<[u8; BCRYPT_SALT_BYTES]>();
+                openssh_cipher(passphrase, &salt, BCRYPT_ROUNDS)?.apply_keystream(&mut private);
+                let mut options = SshWriter::default();
+                options.string(&salt).u32(BCRYPT_ROUNDS);
+                writer.string(CIPHER_AES256_CTR.as_bytes()).string(KDF_BCRYPT.as_bytes()).string(&options.data);
+            }
+            None => { writer.string(CIPHER_NONE.as_bytes()).string(KDF_NONE.as_bytes()).string(&[]); }
+        }
+        writer.u32(1).string(&public).string(&private);
+        Ok(Zeroizing::new(writer.data))
+    }
+
+    /// Decode an `openssh-key-v1` container holding one RSA key, checking the check-ints and padding
+    pub fn from_openssh_private(data: &[u8], passphrase: Option<&[u8]>) -> Result<KeyData, KeyError> {
+        let mut reader = SshReader::new(data);
+        if reader.bytes(OPENSSH_MAGIC.len())? != OPENSSH_MAGIC { return Err(ssh_error("not an openssh-key-v1 container")); }
+        let cipher = reader.string()?;
+        let kdf = reader.string()?;
+        let options = reader.string()?;
+        if reader.u32()? != 1 { return Err(ssh_error("only files with one key are supported")); }
+        let public = KeyData::from_ssh_blob(reader.string()?)?;
+        let mut private = Zeroizing::new(reader.string()?.to_vec());
+        if !reader.is_empty() { return Err(ssh_error("trailing data")); }
+        let block = match (cipher, kdf) {
+            (b"none", b"none") => 8,
+            (b"aes256-ctr", b"bcrypt") => {
+                let passphrase = passphrase.ok_or_else(|| ssh_error("key is encrypted, a passphrase is needed"))?;
+                let mut options = SshReader::new(options);
+                let salt = options.string()?;
+                let rounds = options.u32()?;
+                if rounds > MAX_BCRYPT_ROUNDS {
+                    return Err(ssh_error(&format!("{} bcrypt-pbkdf rounds is above the maximum {}", rounds, MAX_BCRYPT_ROUNDS)));
+                }
+                openssh_cipher(passphrase, salt, rounds)?.apply_keystream(&mut private);
+                16
+            }
+            _ => return Err(ssh_error(&format!("unsupported cipher {} with kdf {}",
+                                               String::from_utf8_lossy(cipher), String::from_utf8_lossy(kdf)))),
+        };
+        if !private.len().is_multiple_of(block) { return Err(ssh_error("private section is not padded to the cipher block")); }
+        let mut reader = SshReader::new(&private);
+        if reader.u32()? != reader.u32()? { return Err(ssh_error("check-ints differ, wrong passphrase or corrupt key")); }
+        if reader.string()? != SSH_RSA.as_bytes() { return Err(ssh_error("private key is not ssh-rsa")); }
+        let (n, e, d, qinv, p, q) = (reader.mpint()?, reader.mpint()?, reader.mpint()?, reader.mpint()?, reader.mpint()?, reader.mpint()?);
+        let comment = String::from_utf8(reader.string()?.to_vec()).map_err(|_| ssh_error("comment is not UTF-8"))?;
+        if reader.data.iter().enumerate().any(|(i, b)| *b as usize != i + 1) { return Err(ssh_error("bad padding")); }
+        if public.key.m != n || public.key.base != e { return Err(ssh_error("private key does not match its public key")); }
+        check_primes(&n, &[&p, &q])?;
+        let crt = KeyCrt {
+            dp: &d % (&p - BigInt::one()),
+            dq: &d % (&q - BigInt::one()),
+            e, p, q, qinv,
+            others: vec![],
+        };
+        let mut key_data = KeyData::new_private(Key { base: d, m: n, crt: Some(crt) }, comment);
+        key_data.header = pem_header(PEM_OPENSSH_PRIVATE);
+        key_data.footer = pem_footer(PEM_OPENSSH_PRIVATE);
+        Ok(key_data)
+    }
+
+    /// Decode the public key blob of RFC 4253 section 6.6
+    pub fn from_ssh_blob(blob: &[u8]) -> Result<KeyData, KeyError> {
+        let mut reader = SshReader::new(blob);
+        let key_type = reader.string()?;
+        if key_type != SSH_RSA.as_bytes() {
+            return Err(ssh_error(&format!("unsupported key type {}, only {} is supported", String::from_utf8_lossy(key_type), SSH_RSA)));
+        }
+        let e = reader.mpint()?;
+        let n = reader.mpint()?;
+        if !reader.is_empty() { return Err(ssh_error("trailing key data")); }
+        Ok(KeyData::new_public(Key::new(e, n), "".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use num_traits::One;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::key_data::{KeyData, KeyFormat};
+    use crate::rsa::keys::key_pair::KeyPair;
+    use crate::rsa::keys::KeyError;
+    use crate::rsa::keys::openssh::*;
+
+    #[test]
+    fn test_ssh_public() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "rsa-rs@host".to_string()),
+            private: KeyData::new_private(key_set.private, "rsa-rs@host".to_string()),
+        };
+        let line = key_pair.public.to_ssh_public()?;
+        println!("{}", line);
+        assert!(line.starts_with("ssh-rsa AAAAB3NzaC1yc2EAAAADAQAB"));
+        assert_eq!(key_pair.private.to_ssh_public()?, line);
+        let public = KeyData::from_ssh_public(&format!("# comment\ncommand=\"ls\",no-pty {}", line))?;
+        assert_eq!(public, key_pair.public);
+        assert_eq!(public.comment, "rsa-rs@host");
+        key_pair.save_format("data/test_ssh".to_string(), &KeyFormat::OpenSsh, true)?;
+        let loaded = KeyPair::try_from("data/test_ssh".to_string())?;
+        assert_eq!(loaded.public, key_pair.public);
+        assert_eq!(loaded.public.comment, "rsa-rs@host");
+        assert!(loaded.check(10).passed());
+        assert!(KeyData::from_ssh_public("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIB user").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_openssh_private() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let private = KeyData::new_private(key_set.private, "rsa-rs@host".to_string());
+        let crt = private.key.crt.as_ref().unwrap();
+        let data = private.to_openssh_private(None)?;
+        let loaded = KeyData::from_openssh_private(&data, None)?;
+        assert_eq!(loaded, private);
+        assert_eq!(loaded.key.crt, private.key.crt);
+        assert_eq!(loaded.comment, "rsa-rs@host");
+        let data = private.to_openssh_private(Some(b"secret"))?;
+        assert!(KeyData::from_openssh_private(&data, None).is_err());
+        assert!(KeyData::from_openssh_private(&data, Some(b"wrong")).is_err());
+        assert_eq!(KeyData::from_openssh_private(&data, Some(b"secret"))?.key.crt, private.key.crt);
+        let mut data = data;
+        let rounds = OPENSSH_MAGIC.len() + 4 + CIPHER_AES256_CTR.len() + 4 + KDF_BCRYPT.len() + 8 + BCRYPT_SALT_BYTES;
+        assert_eq!(data[rounds..rounds + 4], BCRYPT_ROUNDS.to_be_bytes());
+        data[rounds..rounds + 4].copy_from_slice(&(MAX_BCRYPT_ROUNDS + 1).to_be_bytes());
+        assert!(matches!(KeyData::from_openssh_private(&data, Some(b"secret")), Err(KeyError::ParseError(_))));
+        for (p, q) in [(BigInt::one(), private.key.m.clone()), (crt.p.clone(), &crt.q + 2)] {
+            let mut crafted = KeyData::new_private(private.key.clone(), "".to_string());
+            let crafted_crt = crafted.key.crt.as_mut().unwrap();
+            (crafted_crt.p, crafted_crt.q) = (p, q);
+            let data = crafted.to_openssh_private(None)?;
+            assert!(matches!(KeyData::from_openssh_private(&data, None), Err(KeyError::InvalidKey(_))));
+        }
+        let mut data = private.to_openssh_private(None)?;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(KeyData::from_openssh_private(&data, None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::{Display, Formatter};
+use num_bigint::{BigInt, Sign};
+use zeroize::Zeroizing;
+use crate::rsa::keys::{KeyComponents, KeyError};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE};
+use crate::rsa::keys::key_pair::KeyPair;
+
+/// First line of every backup, followed by the encoding, key size and line count
+pub const PAPER_HEADER: &str = "RSA-RS PAPER KEY v1";
+const FINGERPRINT_PREFIX: &str = "fingerprint:";
+/// Characters of data per line, in groups of `GROUP_CHARS`
+const LINE_CHARS: usize = 32;
+const GROUP_CHARS: usize = 4;
+/// More lines than any key needs, to refuse a mistyped count before allocating for it
+const MAX_LINES: usize = 10_000;
+/// RFC 4648 base32, upper case and without padding on paper
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// How the bytes of a paper backup are written down
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaperEncoding {
+    Hex,
+    Base32,
+}
+
+impl TryFrom<&str> for PaperEncoding {
+    type Error = KeyError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "hex" => Ok(PaperEncoding::Hex),
+            "base32" => Ok(PaperEncoding::Base32),
+            _ => Err(KeyError::ParseError(format!("Unknown paper encoding `{}', available: hex, base32", value)))
+        }
+    }
+}
+
+impl Display for PaperEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PaperEncoding::Hex => "hex",
+            PaperEncoding::Base32 => "base32",
+        })
+    }
+}
+
+impl PaperEncoding {
+    /// Bytes on one full line
+    fn line_bytes(&self) -> usize {
+        match self {
+            PaperEncoding::Hex => LINE_CHARS / 2,
+            PaperEncoding::Base32 => LINE_CHARS * 5 / 8,
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> String {
+        match self {
+            PaperEncoding::Hex => data.iter().map(|b| format!("{:02x}", b)).collect(),
+            PaperEncoding::Base32 => {
+                let mut res = String::new();
+                for chunk in data.chunks(5) {
+                    let mut block = [0u8; 8];
+                    block[..chunk.len()].copy_from_slice(chunk);
+                    let bits = u64::from_be_bytes(block);
+                    for i in 0..(chunk.len() * 8).div_ceil(5) {
+                        res.push(BASE32_ALPHABET[(bits >> (59 - i * 5)) as usize & 0x1f] as char);
+                    }
+                }
+                res
+            }
+        }
+    }
+
+    /// Decode `text`, naming the first character that does not belong to the encoding
+    fn decode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let bad = |c: char| format!("`{}' is not a {} character", c, self);
+        match self {
+            PaperEncoding::Hex => {
+                if let Some(c) = text.chars().find(|c| !c.is_ascii_hexdigit()) { return Err(bad(c)); }
+                if !text.len().is_multiple_of(2) { return Err("odd count of hex digits".to_string()); }
+                Ok((0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect())
+            }
+            PaperEncoding::Base32 => {
+                let values = text.chars().map(|c| BASE32_ALPHABET.iter().position(|a| *a as char == c.to_ascii_uppercase()).ok_or_else(|| bad(c)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if matches!(values.len() % 8, 1 | 3 | 6) { return Err("bad count of base32 characters".to_string()); }
+                let mut res = vec![];
+                for chunk in values.chunks(8) {
+                    let bits = chunk.iter().enumerate().fold(0u64, |bits, (i, v)| bits | (*v as u64) << (59 - i * 5));
+                    res.extend_from_slice(&bits.to_be_bytes()[..chunk.len() * 5 / 8]);
+                }
+                Ok(res)
+            }
+        }
+    }
+}
+
+/// CRC-32 of a line and its number, so lines swapped or copied twice are caught too
+fn line_checksum(number: usize, data: &[u8]) -> [u8; 4] {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&(number as u32).to_be_bytes());
+    hasher.update(data);
+    hasher.finalize().to_be_bytes()
+}
+
+fn bad_line(number: usize, msg: &str) -> KeyError {
+    KeyError::BadLine(number, msg.to_string())
+}
+
+impl KeyData {
+    /// Numbered lines for printing, holding e and the primes, each line ending in its checksum.
+    /// d and the CRT values are derived again on restore, which keeps the backup short.
+    pub fn to_paper(&self, encoding: &PaperEncoding) -> Result<Zeroizing<String>, KeyError> {
+        let crt = match (self.mode.as_str(), &self.key.crt) {
+            (MODE_PRIVATE, Some(crt)) => crt,
+            (MODE_PRIVATE, None) => return Err(KeyError::InvalidKey("private key carries no primes, run mode `recover' first".to_string())),
+            _ => return Err(KeyError::InvalidKey("only private keys have paper backups".to_string())),
+        };
+        // Each number is its big endian bytes after a two byte length
+        let mut data = Zeroizing::new(vec![]);
+        for number in [&crt.e].into_iter().chain(crt.primes().iter()) {
+            let bytes = Zeroizing::new(number.to_bytes_be().1);
+            data.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+            data.extend_from_slice(&bytes);
+        }
+        let lines = data.chunks(encoding.line_bytes()).collect::<Vec<_>>();
+        let mut res = Zeroizing::new(format!("{} {} {} bits {} lines\n", PAPER_HEADER, encoding, self.key.m.bits(), lines.len()));
+        for (i, line) in lines.iter().enumerate() {
+            let text = Zeroizing::new(encoding.encode(line));
+            let groups = text.as_bytes().chunks(GROUP_CHARS).map(|g| std::str::from_utf8(g).unwrap()).collect::<Vec<_>>();
+            res.push_str(&format!("{:02}: {}  {}\n", i + 1, groups.join(" "), encoding.encode(&line_checksum(i + 1, line))));
+        }
+        res.push_str(&format!("{} {}\n", FINGERPRINT_PREFIX, self.fingerprint_openssh()?));
+        Ok(res)
+    }
+}
+
+impl KeyPair {
+    /// Rebuild the key pair from the text of `to_paper`, telling which line is wrong when a checksum does not match.
+    /// Spaces within lines do not matter, and neither does the case of base32.
+    pub fn from_paper(text: &str, comment: String, rounds: u32) -> Result<KeyPair, KeyError> {
+        let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+        let header = lines.next().ok_or_else(|| KeyError::ParseError("empty paper backup".to_string()))?;
+        let (encoding, count) = match header.strip_prefix(PAPER_HEADER).map(|h| h.split_whitespace().collect::<Vec<_>>()).as_deref() {
+            Some([encoding, _, "bits", count, "lines"]) => (PaperEncoding::try_from(*encoding)?,
+                                                              count.parse::<usize>().map_err(|_| KeyError::ParseError(format!("bad line count `{}'", count)))?),
+            _ => return Err(KeyError::ParseError(format!("paper backups start with `{} <encoding> <bits> bits <count> lines'", PAPER_HEADER))),
+        };
+        if count > MAX_LINES { return Err(KeyError::ParseError(format!("bad line count `{}'", count))); }
+        let mut found: Vec<Option<Zeroizing<Vec<u8>>>> = vec![None; count];
+        let mut fingerprint = None;
+        for line in lines {
+            if let Some(value) = line.strip_prefix(FINGERPRINT_PREFIX) {
+                fingerprint = Some(value.trim().to_string());
+                continue;
+            }
+            let (number, rest) = line.split_once(':').ok_or_else(|| KeyError::ParseError(format!("line `{}' does not start with its number", line)))?;
+            let number = number.trim().parse::<usize>().map_err(|_| KeyError::ParseError(format!("line `{}' does not start with its number", line)))?;
+            if number == 0 || number > count { return Err(bad_line(number, &format!("beyond the {} lines of the backup", count))); }
+            if found[number - 1].is_some() { return Err(bad_line(number, "appears twice")); }
+            let mut groups = rest.split_whitespace().collect::<Vec<_>>();
+            let checksum = groups.pop().ok_or_else(|| bad_line(number, "empty"))?;
+            let checksum = encoding.decode(checksum).map_err(|e| bad_line(number, &e))?;
+            let data = Zeroizing::new(encoding.decode(&groups.concat()).map_err(|e| bad_line(number, &e))?);
+            if checksum != line_checksum(number, &data) {
+                return Err(bad_line(number, "checksum does not match, look for a typo"));
+            }
+            found[number - 1] = Some(data);
+        }
+        let mut data = Zeroizing::new(vec![]);
+        for (i, line) in found.iter().enumerate() {
+            data.extend_from_slice(line.as_ref().ok_or_else(|| bad_line(i + 1, "missing"))?);
+        }
+
+        let mut numbers = vec![];
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            let len = match rest {
+                [a, b, ..] => u16::from_be_bytes([*a, *b]) as usize,
+                _ => return Err(KeyError::Truncated),
+            };
+            if rest.len() < 2 + len { return Err(KeyError::Truncated); }
+            numbers.push(BigInt::from_bytes_be(Sign::Plus, &rest[2..2 + len]));
+            rest = &rest[2 + len..];
+        }
+        if numbers.len() < 3 { return Err(KeyError::InvalidKey("a paper backup holds e and at least two primes".to_string())); }
+        let e = numbers.remove(0);
+        let key_pair = KeyPair::from_components(&KeyComponents { e: Some(e), primes: numbers, ..Default::default() }, comment, rounds)?;
+        if let Some(fingerprint) = fingerprint {
+            if fingerprint != key_pair.public.fingerprint_openssh()? {
+                return Err(KeyError::InvalidKey(format!("restored key has another fingerprint than {}", fingerprint)));
+            }
+        }
+        Ok(key_pair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::KeyError;
+    use crate::rsa::keys::key_data::KeyData;
+    use crate::rsa::keys::key_pair::KeyPair;
+    use crate::rsa::keys::paper::PaperEncoding;
+
+    #[test]
+    fn test_paper() -> Result<(), Box<dyn Error>> {
+        assert_eq!(PaperEncoding::Base32.encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(PaperEncoding::Base32.decode("mzxw6ytboi")?, b"foobar");
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let private = KeyData::new_private(key_set.private, "".to_string());
+        for encoding in [PaperEncoding::Hex, PaperEncoding::Base32] {
+            let paper = private.to_paper(&encoding)?;
+            let key_pair = KeyPair::from_paper(&paper, "restored".to_string(), 10)?;
+            assert_eq!(key_pair.private.key.crt, private.key.crt);
+            assert_eq!(key_pair.private.key.base, private.key.base);
+            assert_eq!(key_pair.public.comment, "restored");
+
+            // One changed character on line 3
+            let mut lines = paper.lines().map(|l| l.to_string()).collect::<Vec<_>>();
+            let typo = lines[3].chars().nth(5).map(|c| if c == 'a' || c == 'A' { 'b' } else { 'a' }).unwrap();
+            lines[3].replace_range(5..6, &typo.to_string());
+            assert!(matches!(KeyPair::from_paper(&lines.join("\n"), "".to_string(), 10), Err(KeyError::BadLine(3, _))));
+            // Line 2 left out, line 2 copied in place of line 3
+            let mut lines = paper.lines().collect::<Vec<_>>();
+            lines.remove(2);
+            assert!(matches!(KeyPair::from_paper(&lines.join("\n"), "".to_string(), 10), Err(KeyError::BadLine(2, _))));
+            let mut lines = paper.lines().map(|l| l.to_string()).collect::<Vec<_>>();
+            lines[3] = lines[2].replacen("02:", "03:", 1);
+            assert!(matches!(KeyPair::from_paper(&lines.join("\n"), "".to_string(), 10), Err(KeyError::BadLine(3, _))));
+        }
+        Ok(())
+    }
+}
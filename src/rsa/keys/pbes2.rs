@@ -0,0 +1,241 @@
+use aes::Aes256;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::Aead;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use cbc::cipher::block_padding::Pkcs7;
+use num_traits::ToPrimitive;
+use rand::Rng;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+use crate::rsa::asn1::{Der, oid_string};
+use crate::rsa::keys::KeyError;
+use crate::rsa::keys::key_data::{KeyData, KeyFormat, MODE_PRIVATE, pem_footer, pem_header};
+use crate::rsa::keys::native::NATIVE_MAGIC;
+
+pub const PEM_ENCRYPTED_PRIVATE: &str = "ENCRYPTED PRIVATE KEY";
+/// Native keys in the `EncryptedPrivateKeyInfo` layout, a label of their own as only this tool reads them
+pub const PEM_ENCRYPTED_NATIVE: &str = "RSA-RS ENCRYPTED PRIVATE KEY";
+/// PBKDF2-HMAC-SHA256 iterations for new files, as OWASP recommends
+pub const PBKDF2_ITERATIONS: u32 = 600_000;
+/// Most PBKDF2 iterations accepted from a file, about 17 times `PBKDF2_ITERATIONS` so that a crafted file cannot stall loading
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const SALT_BYTES: usize = 16;
+const KEY_BYTES: usize = 32;
+const GCM_NONCE_BYTES: usize = 12;
+const GCM_TAG_BYTES: u32 = 16;
+const CBC_IV_BYTES: usize = 16;
+
+const OID_PBES2: &[u64] = &[1, 2, 840, 113549, 1, 5, 13];
+const OID_PBKDF2: &[u64] = &[1, 2, 840, 113549, 1, 5, 12];
+const OID_HMAC_SHA256: &[u64] = &[1, 2, 840, 113549, 2, 9];
+/// id-aes256-GCM of RFC 5084
+const OID_AES256_GCM: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 1, 46];
+/// id-aes256-CBC, what OpenSSL writes by default
+const OID_AES256_CBC: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 1, 42];
+
+fn pbes2_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("PBES2: {}", msg))
+}
+
+fn wrong_passphrase() -> KeyError {
+    KeyError::Passphrase("wrong passphrase or corrupt key".to_string())
+}
+
+fn pbkdf2_key(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; KEY_BYTES] {
+    let mut key = [0u8; KEY_BYTES];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase, salt, iterations, &mut key);
+    key
+}
+
+/// `PBKDF2-params` with HMAC-SHA256 as PRF
+fn pbkdf2_algorithm(salt: &[u8], iterations: u32) -> Der {
+    Der::Sequence(vec![Der::Oid(OID_PBKDF2.to_vec()), Der::Sequence(vec![
+        Der::OctetString(salt.to_vec()),
+        Der::Integer(iterations.into()),
+        Der::Integer(KEY_BYTES.into()),
+        Der::Sequence(vec![Der::Oid(OID_HMAC_SHA256.to_vec()), Der::Null]),
+    ])])
+}
+
+/// Encrypt with PBES2 of RFC 8018, PBKDF2-HMAC-SHA256 and AES-256-CBC, returning the `AlgorithmIdentifier` and ciphertext.
+/// CBC rather than GCM because OpenSSL refuses AEAD ciphers in `EncryptedPrivateKeyInfo` and PKCS#12.
+pub fn pbes2_encrypt(passphrase: &[u8], data: &[u8], iterations: u32) -> (Der, Vec<u8>) {
+    let salt = rand::thread_rng().gen::<[u8; SALT_BYTES]>();
+    let iv = rand::thread_rng().gen::<[u8; CBC_IV_BYTES]>();
+    let key = pbkdf2_key(passphrase, &salt, iterations);
+    let encrypted = cbc::Encryptor::<Aes256>::new(&key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data);
+    let algorithm = Der::Sequence(vec![Der::Oid(OID_PBES2.to_vec()), Der::Sequence(vec![
+        pbkdf2_algorithm(&salt, iterations),
+        Der::Sequence(vec![Der::Oid(OID_AES256_CBC.to_vec()), Der::OctetString(iv.to_vec())]),
+    ])]);
+    (algorithm, encrypted)
+}
+
+/// Decrypt data encrypted with PBES2, PBKDF2-HMAC-SHA256 and AES-256-CBC, or AES-256-GCM as earlier versions wrote
+pub fn pbes2_decrypt(algorithm: &Der, passphrase: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+    let (oid, params) = match algorithm.as_sequence()? {
+        [oid, params] => (oid.as_oid()?, params.as_sequence()?),
+        _ => return Err(pbes2_error("bad AlgorithmIdentifier")),
+    };
+    if oid != OID_PBES2 {
+        return Err(pbes2_error(&format!("unsupported encryption {}, only PBES2 ({}) is supported", oid_string(oid), oid_string(OID_PBES2))));
+    }
+    let (kdf, scheme) = match params {
+        [kdf, scheme] => (kdf.as_sequence()?, scheme.as_sequence()?),
+        _ => return Err(pbes2_error("bad PBES2-params")),
+    };
+    let kdf_params = match kdf {
+        [oid, params] if oid.as_oid()? == OID_PBKDF2 => params.as_sequence()?,
+        [oid, ..] => return Err(pbes2_error(&format!("unsupported KDF {}, only PBKDF2 is supported", oid_string(oid.as_oid()?)))),
+        _ => return Err(pbes2_error("bad key derivation function")),
+    };
+    let salt = kdf_params.first().ok_or_else(|| pbes2_error("missing salt"))?.as_octet_string()?;
+    let iterations = kdf_params.get(1).ok_or_else(|| pbes2_error("missing iteration count"))?
+        .as_integer()?.to_u32().ok_or_else(|| pbes2_error("bad iteration count"))?;
+    if iterations > MAX_PBKDF2_ITERATIONS { return Err(pbes2_error("iteration count too large")); }
+    // keyLength is optional, the PRF defaults to HMAC-SHA1 which is not supported
+    let prf = kdf_params[2..].iter().find(|p| matches!(p, Der::Sequence(_)));
+    match prf.map(|p| p.as_sequence()).transpose()? {
+        Some([oid, ..]) if oid.as_oid()? == OID_HMAC_SHA256 => {}
+        Some([oid, ..]) => return Err(pbes2_error(&format!("unsupported PRF {}, only hmacWithSHA256 is supported", oid_string(oid.as_oid()?)))),
+        _ => return Err(pbes2_error("PRF hmacWithSHA1 is not supported, only hmacWithSHA256")),
+    }
+    let key = pbkdf2_key(passphrase, salt, iterations);
+    match scheme {
+        [oid, Der::Sequence(gcm)] if oid.as_oid()? == OID_AES256_GCM => {
+            let nonce = gcm.first().ok_or_else(|| pbes2_error("missing GCM nonce"))?.as_octet_string()?;
+            let tag = gcm.get(1).map(|t| t.as_integer()).transpose()?.and_then(|t| t.to_u32()).unwrap_or(12);
+            if nonce.len() != GCM_NONCE_BYTES || tag != GCM_TAG_BYTES {
+                return Err(pbes2_error("only 12 byte GCM nonces and 16 byte tags are supported"));
+            }
+            Aes256Gcm::new(&key.into()).decrypt(Nonce::from_slice(nonce), data).map(Zeroizing::new).map_err(|_| wrong_passphrase())
+        }
+        [oid, Der::OctetString(iv)] if oid.as_oid()? == OID_AES256_CBC => {
+            let iv: [u8; CBC_IV_BYTES] = iv.as_slice().try_into().map_err(|_| pbes2_error("bad CBC IV"))?;
+            cbc::Decryptor::<Aes256>::new(&key.into(), &iv.into())
+                .decrypt_padded_vec_mut::<Pkcs7>(data).map(Zeroizing::new).map_err(|_| wrong_passphrase())
+        }
+        [oid, ..] => Err(pbes2_error(&format!("unsupported cipher {}, only AES-256-GCM and AES-256-CBC are supported", oid_string(oid.as_oid()?)))),
+        _ => Err(pbes2_error("bad encryption scheme")),
+    }
+}
+
+/// Whether `data` is DER of an `EncryptedPrivateKeyInfo`
+pub fn is_encrypted_pkcs8(data: &[u8]) -> bool {
+    match Der::from_der(data) {
+        Ok(Der::Sequence(items)) => matches!(items.as_slice(), [Der::Sequence(algorithm), Der::OctetString(_)]
+            if matches!(algorithm.first(), Some(Der::Oid(oid)) if oid == OID_PBES2)),
+        _ => false,
+    }
+}
+
+/// `EncryptedPrivateKeyInfo` of RFC 5208 section 6 around `plain`
+fn encrypted_private_key_info(passphrase: &[u8], plain: &[u8], iterations: u32) -> Vec<u8> {
+    let (algorithm, encrypted) = pbes2_encrypt(passphrase, plain, iterations);
+    Der::Sequence(vec![algorithm, Der::OctetString(encrypted)]).to_der()
+}
+
+impl KeyData {
+    /// PKCS#8 `EncryptedPrivateKeyInfo` of RFC 5208 section 6
+    pub fn to_encrypted_pkcs8_der(&self, passphrase: &[u8], iterations: u32) -> Result<Vec<u8>, KeyError> {
+        if self.mode != MODE_PRIVATE { return Err(KeyError::InvalidKey("only private keys are encrypted".to_string())); }
+        Ok(encrypted_private_key_info(passphrase, &self.to_pkcs8_der()?, iterations))
+    }
+
+    /// The native encoding in an `EncryptedPrivateKeyInfo`, which keeps the metadata PKCS#8 has no room for
+    pub fn to_encrypted_native(&self, passphrase: &[u8], iterations: u32) -> Result<Vec<u8>, KeyError> {
+        if self.mode != MODE_PRIVATE { return Err(KeyError::InvalidKey("only private keys are encrypted".to_string())); }
+        Ok(encrypted_private_key_info(passphrase, &self.to_native(), iterations))
+    }
+
+    pub fn from_encrypted_pkcs8_der(data: &[u8], passphrase: &[u8]) -> Result<KeyData, KeyError> {
+        match KeyData::from_encrypted_der(data, passphrase)? {
+            (key_data, KeyFormat::Pkcs8) => Ok(key_data),
+            _ => Err(pbes2_error("expect PKCS#8 in EncryptedPrivateKeyInfo")),
+        }
+    }
+
+    /// Decrypt an `EncryptedPrivateKeyInfo` holding PKCS#8 or a native key, and tell which it was
+    pub fn from_encrypted_der(data: &[u8], passphrase: &[u8]) -> Result<(KeyData, KeyFormat), KeyError> {
+        let der = Der::from_der(data)?;
+        let (algorithm, encrypted) = match der.as_sequence()? {
+            [algorithm, encrypted] => (algorithm, encrypted.as_octet_string()?),
+            _ => return Err(pbes2_error("expect EncryptedPrivateKeyInfo")),
+        };
+        let plain = pbes2_decrypt(algorithm, passphrase, encrypted)?;
+        // a wrong passphrase passes the CBC padding check now and then, the key inside tells
+        if plain.starts_with(NATIVE_MAGIC) {
+            return KeyData::from_native(&plain).map(|key_data| (key_data, KeyFormat::Native)).map_err(|_| wrong_passphrase());
+        }
+        let mut key_data = KeyData::from_pkcs8_der(&plain).map_err(|_| wrong_passphrase())?;
+        key_data.header = pem_header(PEM_ENCRYPTED_PRIVATE);
+        key_data.footer = pem_footer(PEM_ENCRYPTED_PRIVATE);
+        Ok((key_data, KeyFormat::Pkcs8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::key_data::{KeyData, KeyFormat};
+    use crate::rsa::keys::key_pair::KeyPair;
+    use crate::rsa::keys::{KeyError, KeyMetadata, KeyUsage};
+    use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+    use aes_gcm::aead::Aead;
+    use rand::Rng;
+    use crate::rsa::asn1::Der;
+    use crate::rsa::keys::pbes2::*;
+
+    /// `EncryptedPrivateKeyInfo` with AES-256-GCM, as earlier versions wrote it
+    fn to_gcm_pkcs8_der(key: &KeyData, passphrase: &[u8], iterations: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let salt = rand::thread_rng().gen::<[u8; SALT_BYTES]>();
+        let nonce = rand::thread_rng().gen::<[u8; GCM_NONCE_BYTES]>();
+        let key_bytes = pbkdf2_key(passphrase, &salt, iterations);
+        // RFC 5084 puts the tag at the end of the ciphertext, as the aead crate does
+        let encrypted = Aes256Gcm::new(&key_bytes.into()).encrypt(Nonce::from_slice(&nonce), key.to_pkcs8_der()?.as_slice()).unwrap();
+        let algorithm = Der::Sequence(vec![Der::Oid(OID_PBES2.to_vec()), Der::Sequence(vec![
+            pbkdf2_algorithm(&salt, iterations),
+            Der::Sequence(vec![Der::Oid(OID_AES256_GCM.to_vec()), Der::Sequence(vec![
+                Der::OctetString(nonce.to_vec()),
+                Der::Integer(GCM_TAG_BYTES.into()),
+            ])]),
+        ])]);
+        Ok(Der::Sequence(vec![algorithm, Der::OctetString(encrypted)]).to_der())
+    }
+
+    #[test]
+    fn test_encrypted_pkcs8() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "".to_string()),
+            private: KeyData::new_private(key_set.private, "".to_string()),
+        };
+        let der = key_pair.private.to_encrypted_pkcs8_der(b"secret", 1000)?;
+        assert!(is_encrypted_pkcs8(&der));
+        assert!(KeyData::from_encrypted_pkcs8_der(&der, b"wrong").is_err());
+        assert_eq!(KeyData::from_encrypted_pkcs8_der(&der, b"secret")?.key.crt, key_pair.private.key.crt);
+        let gcm = to_gcm_pkcs8_der(&key_pair.private, b"secret", 1000)?;
+        assert!(KeyData::from_encrypted_pkcs8_der(&gcm, b"wrong").is_err());
+        assert_eq!(KeyData::from_encrypted_pkcs8_der(&gcm, b"secret")?.key.crt, key_pair.private.key.crt);
+        for format in [KeyFormat::Native, KeyFormat::OpenSsh] {
+            key_pair.save_encrypted("data/test_encrypted".to_string(), &format, true, b"secret")?;
+            let loaded = KeyPair::load_with_passphrase("data/test_encrypted".to_string(), &mut || Ok(b"secret".to_vec()))?;
+            assert_eq!(loaded.private.key.crt, key_pair.private.key.crt);
+        }
+        key_pair.private.metadata = KeyMetadata { created: Some(1), expires: Some(2_000_000_000), owner: "bob".to_string(), usages: Some(vec![KeyUsage::Decrypt]) };
+        for armored in [true, false] {
+            key_pair.private.save_encrypted("data/test_encrypted_native".to_string(), &KeyFormat::Native, armored, b"secret")?;
+            let (loaded, format) = KeyData::load_with_format("data/test_encrypted_native".to_string(), &mut || Ok(b"secret".to_vec()))?;
+            assert_eq!((format, &loaded.metadata), (KeyFormat::Native, &key_pair.private.metadata));
+            assert_eq!(KeyData::native_version("data/test_encrypted_native")?, None);
+        }
+        let algorithm = Der::Sequence(vec![Der::Oid(OID_PBES2.to_vec()), Der::Sequence(vec![
+            pbkdf2_algorithm(&[0; SALT_BYTES], MAX_PBKDF2_ITERATIONS + 1),
+            Der::Sequence(vec![Der::Oid(OID_AES256_CBC.to_vec()), Der::OctetString(vec![0; CBC_IV_BYTES])]),
+        ])]);
+        assert!(matches!(pbes2_decrypt(&algorithm, b"secret", &[0; 16]), Err(KeyError::ParseError(_))));
+        assert!(key_pair.private.save_encrypted("data/test_encrypted_pkcs1".to_string(), &KeyFormat::Pkcs1, true, b"secret").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use num_bigint::{BigInt, Sign};
+use num_traits::Zero;
+use zeroize::Zeroizing;
+use crate::rsa::asn1::Der;
+use crate::rsa::keys::{CrtPrime, Key, KeyCrt, KeyError};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE, pem_footer, pem_header};
+
+pub const PEM_RSA_PUBLIC: &str = "RSA PUBLIC KEY";
+pub const PEM_RSA_PRIVATE: &str = "RSA PRIVATE KEY";
+
+/// `Version` of `RSAPrivateKey`, `two-prime` or `multi`
+const VERSION_TWO_PRIME: u32 = 0;
+const VERSION_MULTI: u32 = 1;
+
+fn integers(items: &[Der]) -> Result<Vec<BigInt>, KeyError> {
+    items.iter().map(|i| match i.as_integer()? {
+        i if i.sign() == Sign::Minus => Err(KeyError::ParseError("PKCS#1: negative integer".to_string())),
+        i => Ok(i.clone()),
+    }).collect()
+}
+
+impl KeyData {
+    /// PEM label of this key in PKCS#1
+    pub fn pkcs1_label(&self) -> &'static str {
+        match self.mode.as_str() {
+            MODE_PRIVATE => PEM_RSA_PRIVATE,
+            _ => PEM_RSA_PUBLIC,
+        }
+    }
+
+    /// Encode as `RSAPublicKey` or `RSAPrivateKey`, RFC 8017 appendix A.1
+    pub fn to_pkcs1_der(&self) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+        let key = &self.key;
+        if self.mode != MODE_PRIVATE {
+            return Ok(Zeroizing::new(Der::Sequence(vec![Der::Integer(key.m.clone()), Der::Integer(key.base.clone())]).to_der()));
+        }
+        let crt = key.crt.as_ref()
+            .ok_or_else(|| KeyError::InvalidKey("PKCS#1 private keys need the primes, run mode `recover' first".to_string()))?;
+        let version = if crt.others.is_empty() { VERSION_TWO_PRIME } else { VERSION_MULTI };
+        let mut items = vec![Der::Integer(BigInt::from(version)), Der::Integer(key.m.clone()), Der::Integer(crt.e.clone()), Der::Integer(key.base.clone())];
+        items.extend([&crt.p, &crt.q, &crt.dp, &crt.dq, &crt.qinv].map(|i| Der::Integer(i.clone())));
+        if !crt.others.is_empty() {
+            items.push(Der::Sequence(crt.others.iter().map(|o| Der::Sequence(vec![
+                Der::Integer(o.r.clone()),
+                Der::Integer(o.d.clone()),
+                Der::Integer(o.t.clone()),
+            ])).collect()));
+        }
+        Ok(Zeroizing::new(Der::Sequence(items).to_der()))
+    }
+
+    /// Decode `RSAPublicKey` or `RSAPrivateKey`, telling them apart by their structure
+    pub fn from_pkcs1_der(data: &[u8]) -> Result<KeyData, KeyError> {
+        let der = Der::from_der(data)?;
+        let items = der.as_sequence()?;
+        let mut key_data = match items.len() {
+            2 => {
+                let values = integers(items)?;
+                KeyData::new_public(Key::new(values[1].clone(), values[0].clone()), "".to_string())
+            }
+            9 | 10 => {
+                let values = integers(&items[..9])?;
+                let version = &values[0];
+                let others = match items.get(9) {
+                    Some(others) if version == &BigInt::from(VERSION_MULTI) => others.as_sequence()?.iter().map(|other| {
+                        match integers(other.as_sequence()?)?.as_slice() {
+                            [r, d, t] => Ok(CrtPrime { r: r.clone(), d: d.clone(), t: t.clone() }),
+                            _ => Err(KeyError::ParseError("PKCS#1: bad OtherPrimeInfo".to_string())),
+                        }
+                    }).collect::<Result<Vec<_>, _>>()?,
+                    None if version.is_zero() => vec![],
+                    _ => return Err(KeyError::ParseError(format!("PKCS#1: unsupported RSAPrivateKey version {}", version))),
+                };
+                let crt = KeyCrt {
+                    e: values[2].clone(),
+                    p: values[4].clone(),
+                    q: values[5].clone(),
+                    dp: values[6].clone(),
+                    dq: values[7].clone(),
+                    qinv: values[8].clone(),
+                    others,
+                };
+                crt.check(&values[1])?;
+                KeyData::new_private(Key { base: values[3].clone(), m: values[1].clone(), crt: Some(crt) }, "".to_string())
+            }
+            n => return Err(KeyError::ParseError(format!("PKCS#1: expect 2, 9 or 10 values, got {}", n))),
+        };
+        if key_data.key.m.is_zero() {
+            return Err(KeyError::InvalidKey("modulus is zero".to_string()));
+        }
+        let label = key_data.pkcs1_label();
+        key_data.header = pem_header(label);
+        key_data.footer = pem_footer(label);
+        Ok(key_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use num_traits::One;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::KeyError;
+    use crate::rsa::keys::key_data::{KeyData, KeyFormat};
+    use crate::rsa::keys::key_pair::KeyPair;
+
+    #[test]
+    fn test_pkcs1() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key_primes(3)?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "".to_string()),
+            private: KeyData::new_private(key_set.private, "".to_string()),
+        };
+        let der = key_pair.private.to_pkcs1_der()?;
+        let private = KeyData::from_pkcs1_der(&der)?;
+        assert_eq!(private, key_pair.private);
+        assert_eq!(private.key.crt, key_pair.private.key.crt);
+        assert_eq!(private.to_pkcs1_der()?, der);
+        let crt = private.key.crt.as_ref().unwrap();
+        for (e, p, q) in [(crt.e.clone(), BigInt::one(), private.key.m.clone()), (crt.e.clone(), crt.p.clone(), &crt.q + 2), (BigInt::one(), crt.p.clone(), crt.q.clone())] {
+            let mut crafted = KeyData::new_private(private.key.clone(), "".to_string());
+            let crafted_crt = crafted.key.crt.as_mut().unwrap();
+            (crafted_crt.e, crafted_crt.p, crafted_crt.q) = (e, p, q);
+            assert!(matches!(KeyData::from_pkcs1_der(&crafted.to_pkcs1_der()?), Err(KeyError::InvalidKey(_))));
+        }
+        key_pair.save_format("data/test_pkcs1".to_string(), &KeyFormat::Pkcs1, true)?;
+        let loaded = KeyPair::try_from("data/test_pkcs1".to_string())?;
+        assert_eq!(loaded.public, key_pair.public);
+        assert_eq!(loaded.private.key.crt, key_pair.private.key.crt);
+        assert!(loaded.check(10).passed());
+        key_pair.save_format("data/test_pkcs1_der".to_string(), &KeyFormat::Pkcs1, false)?;
+        let loaded = KeyPair::try_from("data/test_pkcs1_der".to_string())?;
+        assert_eq!(loaded.private.key.crt, key_pair.private.key.crt);
+        key_pair.private.key.crt = None;
+        assert!(key_pair.private.to_pkcs1_der().is_err());
+        Ok(())
+    }
+}
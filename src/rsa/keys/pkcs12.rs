@@ -0,0 +1,417 @@
+use std::io::Cursor;
+use hmac::{Hmac, Mac};
+use num_traits::ToPrimitive;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+use crate::rsa::asn1::{Der, oid_string};
+use crate::rsa::keys::{Key, KeyError, KeyReader, PEM_SPLIT};
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE, pem_footer, pem_header};
+use crate::rsa::keys::key_pair::KeyPair;
+use crate::rsa::keys::key_writer::write_armored;
+use crate::rsa::keys::pbes2::{MAX_PBKDF2_ITERATIONS, pbes2_decrypt, pbes2_encrypt};
+
+pub const PEM_CERTIFICATE: &str = "CERTIFICATE";
+/// Iterations of the MAC key derivation, the OpenSSL default
+pub const PKCS12_MAC_ITERATIONS: u32 = 2048;
+const PFX_VERSION: u32 = 3;
+const MAC_SALT_BYTES: usize = 16;
+/// `ID` of RFC 7292 appendix B.3 for MAC keys
+const MAC_KEY_ID: u8 = 3;
+/// Output and block size of SHA-256, `u` and `v` of RFC 7292 appendix B.2
+const HASH_BYTES: usize = 32;
+const HASH_BLOCK_BYTES: usize = 64;
+/// Context specific, primitive `[0]`, the implicitly tagged encrypted content of PKCS#7
+const TAG_IMPLICIT_0: u8 = 0x80;
+
+const OID_PKCS7_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 1];
+const OID_PKCS7_ENCRYPTED_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 6];
+const OID_KEY_BAG: &[u64] = &[1, 2, 840, 113549, 1, 12, 10, 1, 1];
+const OID_SHROUDED_KEY_BAG: &[u64] = &[1, 2, 840, 113549, 1, 12, 10, 1, 2];
+const OID_CERT_BAG: &[u64] = &[1, 2, 840, 113549, 1, 12, 10, 1, 3];
+const OID_X509_CERTIFICATE: &[u64] = &[1, 2, 840, 113549, 1, 9, 22, 1];
+const OID_FRIENDLY_NAME: &[u64] = &[1, 2, 840, 113549, 1, 9, 20];
+const OID_LOCAL_KEY_ID: &[u64] = &[1, 2, 840, 113549, 1, 9, 21];
+const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+
+/// What older tools write by default, named in errors so the file can be exported again
+const LEGACY_ALGORITHMS: &[(&[u64], &str)] = &[
+    (&[1, 2, 840, 113549, 1, 12, 1, 1], "pbeWithSHAAnd128BitRC4"),
+    (&[1, 2, 840, 113549, 1, 12, 1, 2], "pbeWithSHAAnd40BitRC4"),
+    (&[1, 2, 840, 113549, 1, 12, 1, 3], "pbeWithSHAAnd3-KeyTripleDES-CBC"),
+    (&[1, 2, 840, 113549, 1, 12, 1, 4], "pbeWithSHAAnd2-KeyTripleDES-CBC"),
+    (&[1, 2, 840, 113549, 1, 12, 1, 5], "pbeWithSHAAnd128BitRC2-CBC"),
+    (&[1, 2, 840, 113549, 1, 12, 1, 6], "pbeWithSHAAnd40BitRC2-CBC"),
+    (&[1, 2, 840, 113549, 1, 5, 3], "pbeWithMD5AndDES-CBC"),
+    (&[1, 2, 840, 113549, 1, 5, 10], "pbeWithSHA1AndDES-CBC"),
+    (&[1, 3, 14, 3, 2, 26], "SHA-1 MAC"),
+    (&[1, 2, 840, 113549, 2, 5], "MD5 MAC"),
+];
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn pkcs12_error(msg: &str) -> KeyError {
+    KeyError::ParseError(format!("PKCS#12: {}", msg))
+}
+
+/// Error for `oid` when it is a legacy algorithm, with the OpenSSL command that writes a readable file
+fn legacy_error(oid: &[u64]) -> Option<KeyError> {
+    LEGACY_ALGORITHMS.iter().find(|(o, _)| *o == oid).map(|(_, name)| pkcs12_error(&format!(
+        "legacy algorithm {} ({}) is not supported, export again with `openssl pkcs12 -export -keypbe AES-256-CBC -certpbe AES-256-CBC -macalg sha256'",
+        name, oid_string(oid))))
+}
+
+fn wrong_password() -> KeyError {
+    KeyError::Passphrase("wrong password or corrupt PKCS#12 file, the MAC does not match".to_string())
+}
+
+/// The password as the PKCS#12 KDF takes it, a NUL terminated big endian `BMPString`
+fn bmp_password(password: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+    let password = std::str::from_utf8(password).map_err(|_| KeyError::Passphrase("PKCS#12 passwords must be UTF-8".to_string()))?;
+    Ok(Zeroizing::new(password.encode_utf16().chain([0]).flat_map(|c| c.to_be_bytes()).collect()))
+}
+
+/// Key derivation of RFC 7292 appendix B.2 with SHA-256, only used for the MAC key since PBES2 brings its own
+fn pkcs12_kdf(password: &[u8], salt: &[u8], id: u8, iterations: u32, len: usize) -> Zeroizing<Vec<u8>> {
+    let fill = |data: &[u8]| data.iter().cycle().take(data.len().div_ceil(HASH_BLOCK_BYTES) * HASH_BLOCK_BYTES).copied().collect::<Vec<_>>();
+    let mut input = Zeroizing::new([fill(salt), fill(password)].concat());
+    let mut res = Zeroizing::new(Vec::with_capacity(len + HASH_BYTES));
+    while res.len() < len {
+        let mut hash = Sha256::new().chain_update([id; HASH_BLOCK_BYTES]).chain_update(&*input).finalize();
+        for _ in 1..iterations {
+            hash = Sha256::digest(hash);
+        }
+        res.extend_from_slice(&hash);
+        // Every block of the input becomes block + hash repeated + 1, modulo 2^(8 * v)
+        let addend = hash.iter().cycle().take(HASH_BLOCK_BYTES).copied().collect::<Vec<_>>();
+        for block in input.chunks_mut(HASH_BLOCK_BYTES) {
+            let mut carry = 1u16;
+            for (b, a) in block.iter_mut().zip(&addend).rev() {
+                let sum = *b as u16 + *a as u16 + carry;
+                *b = sum as u8;
+                carry = sum >> 8;
+            }
+        }
+    }
+    res.truncate(len);
+    res
+}
+
+fn mac(password: &[u8], salt: &[u8], iterations: u32, data: &[u8]) -> Result<HmacSha256, KeyError> {
+    let key = pkcs12_kdf(&bmp_password(password)?, salt, MAC_KEY_ID, iterations, HASH_BYTES);
+    let mut mac = HmacSha256::new_from_slice(&key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    Ok(mac)
+}
+
+/// Accept everything but the legacy algorithms, which get a helpful error instead of an unknown OID
+fn decrypt(algorithm: &Der, password: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+    if let Some(error) = algorithm.as_sequence()?.first().map(|oid| oid.as_oid()).transpose()?.and_then(legacy_error) {
+        return Err(error);
+    }
+    pbes2_decrypt(algorithm, password, data)
+}
+
+fn attribute(oid: &[u64], value: Der) -> Der {
+    Der::Sequence(vec![Der::Oid(oid.to_vec()), Der::Set(vec![value])])
+}
+
+/// `SafeBag` of RFC 7292 section 4.2
+fn safe_bag(oid: &[u64], value: Der, attributes: Vec<Der>) -> Der {
+    Der::Sequence(vec![Der::Oid(oid.to_vec()), Der::Context(0, vec![value]), Der::Set(attributes)])
+}
+
+/// `ContentInfo` of PKCS#7 holding `data` in the clear
+fn data_content(data: Vec<u8>) -> Der {
+    Der::Sequence(vec![Der::Oid(OID_PKCS7_DATA.to_vec()), Der::Context(0, vec![Der::OctetString(data)])])
+}
+
+/// `ContentInfo` of PKCS#7 holding `data` as `EncryptedData` under `password`
+fn encrypted_content(password: &[u8], data: &[u8], iterations: u32) -> Der {
+    let (algorithm, encrypted) = pbes2_encrypt(password, data, iterations);
+    Der::Sequence(vec![Der::Oid(OID_PKCS7_ENCRYPTED_DATA.to_vec()), Der::Context(0, vec![Der::Sequence(vec![
+        Der::Integer(0.into()),
+        Der::Sequence(vec![Der::Oid(OID_PKCS7_DATA.to_vec()), algorithm, Der::Raw(TAG_IMPLICIT_0, encrypted)]),
+    ])])])
+}
+
+/// The `[0]` explicit content of a `ContentInfo` as an `OCTET STRING`
+fn explicit_octets(content: &Der) -> Result<&[u8], KeyError> {
+    match content {
+        Der::Context(0, items) => match items.as_slice() {
+            [data] => data.as_octet_string(),
+            _ => Err(pkcs12_error("bad ContentInfo")),
+        },
+        _ => Err(pkcs12_error("bad ContentInfo")),
+    }
+}
+
+/// `SafeContents` of one `ContentInfo` of the `AuthenticatedSafe`, decrypted when needed
+fn safe_contents(content_info: &Der, password: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+    match content_info.as_sequence()? {
+        [oid, content] if oid.as_oid()? == OID_PKCS7_DATA => Ok(Zeroizing::new(explicit_octets(content)?.to_vec())),
+        [oid, Der::Context(0, content)] if oid.as_oid()? == OID_PKCS7_ENCRYPTED_DATA => {
+            let encrypted_content_info = match content.as_slice() {
+                [encrypted_data] => match encrypted_data.as_sequence()? {
+                    [_version, info, ..] => info.as_sequence()?,
+                    _ => return Err(pkcs12_error("bad EncryptedData")),
+                },
+                _ => return Err(pkcs12_error("bad EncryptedData")),
+            };
+            match encrypted_content_info {
+                [_, algorithm, Der::Raw(TAG_IMPLICIT_0, encrypted)] => decrypt(algorithm, password, encrypted),
+                [_, _] => Ok(Zeroizing::new(vec![])),
+                _ => Err(pkcs12_error("bad EncryptedContentInfo")),
+            }
+        }
+        [oid, ..] => Err(pkcs12_error(&format!("unsupported content type {}, only data and encryptedData are supported", oid_string(oid.as_oid()?)))),
+        _ => Err(pkcs12_error("bad ContentInfo")),
+    }
+}
+
+/// Value of the `friendlyName` attribute among `attributes`
+fn friendly_name(attributes: &[Der]) -> Option<String> {
+    attributes.iter().find_map(|attribute| match attribute.as_sequence().ok()? {
+        [oid, Der::Set(values)] if oid.as_oid().ok()? == OID_FRIENDLY_NAME => match values.first()? {
+            Der::BmpString(name) => Some(name.clone()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Public key of a DER X.509 certificate, read from its `SubjectPublicKeyInfo`
+pub fn certificate_key(der: &[u8]) -> Result<KeyData, KeyError> {
+    let error = || pkcs12_error("expect an X.509 certificate");
+    let certificate = Der::from_der(der)?;
+    let tbs = match certificate.as_sequence()? {
+        [tbs, _, _] => tbs.as_sequence()?,
+        _ => return Err(error()),
+    };
+    // The version is an optional `[0]` before serial number, signature, issuer, validity and subject
+    let skip = match tbs.first() {
+        Some(Der::Context(0, _)) => 1,
+        _ => 0,
+    };
+    KeyData::from_pkcs8_der(&tbs.get(skip + 5).ok_or_else(error)?.to_der())
+}
+
+/// DER of a certificate given as PEM or DER
+pub fn certificate_der(data: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let der = KeyReader::new(Box::new(Cursor::new(data.to_vec())))?.read_all()?;
+    certificate_key(&der)?;
+    Ok(der)
+}
+
+/// PEM of a DER certificate
+pub fn certificate_pem(der: &[u8]) -> Result<String, KeyError> {
+    let mut data = vec![];
+    write_armored(&mut data, pem_header(PEM_CERTIFICATE), pem_footer(PEM_CERTIFICATE), PEM_SPLIT, der)?;
+    Ok(String::from_utf8(data).expect("armored certificates are ASCII"))
+}
+
+fn same_key(a: &KeyData, b: &KeyData) -> Result<bool, KeyError> {
+    Ok(a.public_numbers()? == b.public_numbers()?)
+}
+
+impl KeyPair {
+    /// PKCS#12 `PFX` of RFC 7292 holding the private key and optionally its certificate in DER.
+    /// Both are encrypted with PBES2, PBKDF2-HMAC-SHA256 and AES-256-CBC as Java, Windows and OpenSSL 3 read them,
+    /// and the whole file is protected by an HMAC-SHA256 MAC.
+    pub fn to_pkcs12_der(&self, certificate: Option<&[u8]>, password: &[u8], iterations: u32) -> Result<Vec<u8>, KeyError> {
+        if self.private.mode != MODE_PRIVATE { return Err(KeyError::InvalidKey("only private keys go into PKCS#12 files".to_string())); }
+        let local_key_id = self.public.fingerprint()?;
+        let mut attributes = vec![attribute(OID_LOCAL_KEY_ID, Der::OctetString(local_key_id))];
+        if !self.private.comment.is_empty() {
+            attributes.push(attribute(OID_FRIENDLY_NAME, Der::BmpString(self.private.comment.clone())));
+        }
+        let mut contents = vec![];
+        if let Some(certificate) = certificate {
+            if !same_key(&certificate_key(certificate)?, &self.public)? {
+                return Err(KeyError::InvalidKey("the certificate is for another key".to_string()));
+            }
+            let bag = safe_bag(OID_CERT_BAG, Der::Sequence(vec![
+                Der::Oid(OID_X509_CERTIFICATE.to_vec()),
+                Der::Context(0, vec![Der::OctetString(certificate.to_vec())]),
+            ]), attributes.clone());
+            contents.push(encrypted_content(password, &Der::Sequence(vec![bag]).to_der(), iterations));
+        }
+        let private = self.private.to_pkcs8_der()?;
+        let (algorithm, encrypted) = pbes2_encrypt(password, &private, iterations);
+        let bag = safe_bag(OID_SHROUDED_KEY_BAG, Der::Sequence(vec![algorithm, Der::OctetString(encrypted)]), attributes);
+        contents.push(data_content(Der::Sequence(vec![bag]).to_der()));
+
+        let authenticated_safe = Der::Sequence(contents).to_der();
+        let salt = rand::thread_rng().gen::<[u8; MAC_SALT_BYTES]>();
+        let digest = mac(password, &salt, PKCS12_MAC_ITERATIONS, &authenticated_safe)?.finalize().into_bytes();
+        Ok(Der::Sequence(vec![
+            Der::Integer(PFX_VERSION.into()),
+            data_content(authenticated_safe),
+            Der::Sequence(vec![
+                Der::Sequence(vec![Der::Sequence(vec![Der::Oid(OID_SHA256.to_vec()), Der::Null]), Der::OctetString(digest.to_vec())]),
+                Der::OctetString(salt.to_vec()),
+                Der::Integer(PKCS12_MAC_ITERATIONS.into()),
+            ]),
+        ]).to_der())
+    }
+
+    /// Read a PKCS#12 file after checking its MAC, returning the key pair and the DER certificate of its key if there is one.
+    /// Certificates of other keys, like those of a CA chain, are skipped.
+    pub fn from_pkcs12_der(data: &[u8], password: &[u8]) -> Result<(KeyPair, Option<Vec<u8>>), KeyError> {
+        let pfx = Der::from_der(data)?;
+        let (auth_safe, mac_data) = match pfx.as_sequence()? {
+            [version, auth_safe, rest @ ..] => {
+                if version.as_integer()?.to_u32() != Some(PFX_VERSION) { return Err(pkcs12_error("unsupported PFX version")); }
+                (auth_safe, rest.first().ok_or_else(|| pkcs12_error("no MAC, only password integrity is supported"))?.as_sequence()?)
+            }
+            _ => return Err(pkcs12_error("expect PFX")),
+        };
+        let authenticated_safe = match auth_safe.as_sequence()? {
+            [oid, content] if oid.as_oid()? == OID_PKCS7_DATA => explicit_octets(content)?,
+            [oid, ..] => return Err(pkcs12_error(&format!("unsupported integrity mode {}, only password integrity is supported", oid_string(oid.as_oid()?)))),
+            _ => return Err(pkcs12_error("bad ContentInfo")),
+        };
+
+        let (digest_info, salt, iterations) = match mac_data {
+            [digest_info, salt, rest @ ..] => (digest_info.as_sequence()?, salt.as_octet_string()?, match rest.first() {
+                Some(iterations) => match iterations.as_integer()?.to_u32().filter(|i| *i > 0) {
+                    Some(iterations) if iterations > MAX_PBKDF2_ITERATIONS => return Err(pkcs12_error("MAC iteration count too large")),
+                    Some(iterations) => iterations,
+                    None => return Err(pkcs12_error("bad MAC iteration count")),
+                },
+                None => 1,
+            }),
+            _ => return Err(pkcs12_error("bad MacData")),
+        };
+        let (algorithm, digest) = match digest_info {
+            [algorithm, digest] => (algorithm.as_sequence()?.first().ok_or_else(|| pkcs12_error("bad DigestInfo"))?.as_oid()?, digest.as_octet_string()?),
+            _ => return Err(pkcs12_error("bad DigestInfo")),
+        };
+        if algorithm != OID_SHA256 {
+            return Err(legacy_error(algorithm).unwrap_or_else(|| pkcs12_error(&format!("unsupported MAC digest {}, only SHA-256 is supported", oid_string(algorithm)))));
+        }
+        mac(password, salt, iterations, authenticated_safe)?.verify_slice(digest).map_err(|_| wrong_password())?;
+
+        let mut keys = vec![];
+        let mut certificates = vec![];
+        for content_info in Der::from_der(authenticated_safe)?.as_sequence()? {
+            let contents = safe_contents(content_info, password)?;
+            if contents.is_empty() { continue; }
+            for bag in Der::from_der(&contents)?.as_sequence()? {
+                let (oid, value, attributes) = match bag.as_sequence()? {
+                    [oid, Der::Context(0, value), rest @ ..] if value.len() == 1 => (oid.as_oid()?, &value[0], match rest.first() {
+                        Some(Der::Set(attributes)) => attributes.as_slice(),
+                        _ => &[],
+                    }),
+                    _ => return Err(pkcs12_error("bad SafeBag")),
+                };
+                match oid {
+                    OID_SHROUDED_KEY_BAG => match value.as_sequence()? {
+                        [algorithm, encrypted] => {
+                            let private = decrypt(algorithm, password, encrypted.as_octet_string()?)?;
+                            keys.push((KeyData::from_pkcs8_der(&private)?, friendly_name(attributes)));
+                        }
+                        _ => return Err(pkcs12_error("bad EncryptedPrivateKeyInfo")),
+                    },
+                    OID_KEY_BAG => keys.push((KeyData::from_pkcs8_der(&value.to_der())?, friendly_name(attributes))),
+                    OID_CERT_BAG => match value.as_sequence()? {
+                        [cert_type, certificate] if cert_type.as_oid()? == OID_X509_CERTIFICATE => certificates.push(explicit_octets(certificate)?.to_vec()),
+                        _ => {}
+                    },
+                    // CRLs, secrets and nested bags carry nothing for us
+                    _ => {}
+                }
+            }
+        }
+
+        let (mut private, name) = match keys.len() {
+            1 => keys.remove(0),
+            0 => return Err(pkcs12_error("no private key found")),
+            n => return Err(pkcs12_error(&format!("holds {} private keys, only one is supported", n))),
+        };
+        let (e, n) = private.public_numbers()?;
+        let mut public = KeyData::new_public(Key::new(e.clone(), n.clone()), name.clone().unwrap_or_default());
+        private.comment = name.unwrap_or_default();
+        let bits = private.key.m.bits() as usize;
+        public.generate_header_footer_bits(bits);
+        private.generate_header_footer_bits(bits);
+        let certificate = certificates.into_iter()
+            .find(|c| certificate_key(c).and_then(|key| same_key(&key, &public)).unwrap_or(false));
+        Ok((KeyPair { public, private }, certificate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::rsa::asn1::Der;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::KeyError;
+    use crate::rsa::keys::key_data::KeyData;
+    use crate::rsa::keys::key_pair::KeyPair;
+    use crate::rsa::keys::pbes2::MAX_PBKDF2_ITERATIONS;
+    use crate::rsa::keys::pkcs12::{certificate_der, certificate_pem};
+
+    /// Certificate shaped DER around the public key, the signature is not checked by anything here
+    fn fake_certificate(key: &KeyData) -> Result<Vec<u8>, KeyError> {
+        let algorithm = Der::Sequence(vec![Der::Oid(vec![1, 2, 840, 113549, 1, 1, 11]), Der::Null]);
+        Ok(Der::Sequence(vec![
+            Der::Sequence(vec![
+                Der::Context(0, vec![Der::Integer(2.into())]),
+                Der::Integer(1.into()),
+                algorithm.clone(),
+                Der::Sequence(vec![]),
+                Der::Sequence(vec![Der::Raw(0x17, b"240101000000Z".to_vec()), Der::Raw(0x17, b"340101000000Z".to_vec())]),
+                Der::Sequence(vec![]),
+                Der::from_der(&key.to_pkcs8_der()?)?,
+            ]),
+            algorithm,
+            Der::BitString(vec![0; 64]),
+        ]).to_der())
+    }
+
+    #[test]
+    fn test_pkcs12() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "alice".to_string()),
+            private: KeyData::new_private(key_set.private, "alice".to_string()),
+        };
+        let certificate = fake_certificate(&key_pair.public)?;
+        assert_eq!(certificate_der(certificate_pem(&certificate)?.as_bytes())?, certificate);
+
+        let der = key_pair.to_pkcs12_der(Some(&certificate), "pässword".as_bytes(), 1000)?;
+        let (loaded, loaded_certificate) = KeyPair::from_pkcs12_der(&der, "pässword".as_bytes())?;
+        assert_eq!(loaded.private.key.crt, key_pair.private.key.crt);
+        assert_eq!(loaded.public.key, key_pair.public.key);
+        assert_eq!(loaded.private.comment, "alice");
+        assert_eq!(loaded_certificate, Some(certificate));
+        assert!(matches!(KeyPair::from_pkcs12_der(&der, b"wrong"), Err(KeyError::Passphrase(_))));
+
+        let (_, loaded_certificate) = KeyPair::from_pkcs12_der(&key_pair.to_pkcs12_der(None, b"secret", 1000)?, b"secret")?;
+        assert_eq!(loaded_certificate, None);
+
+        let mut pfx = Der::from_der(&der)?;
+        if let Der::Sequence(items) = &mut pfx {
+            if let Der::Sequence(mac_data) = &mut items[2] { mac_data[2] = Der::Integer((MAX_PBKDF2_ITERATIONS + 1).into()); }
+        }
+        let error = KeyPair::from_pkcs12_der(&pfx.to_der(), "pässword".as_bytes()).unwrap_err().to_string();
+        assert!(error.contains("MAC iteration count too large"), "{}", error);
+
+        // Files of OpenSSL 1.1 and older use a SHA-1 MAC and RC2 or 3DES
+        let mut pfx = Der::from_der(&der)?;
+        if let Der::Sequence(items) = &mut pfx {
+            items[2] = Der::Sequence(vec![
+                Der::Sequence(vec![Der::Sequence(vec![Der::Oid(vec![1, 3, 14, 3, 2, 26]), Der::Null]), Der::OctetString(vec![0; 20])]),
+                Der::OctetString(vec![0; 8]),
+                Der::Integer(2048.into()),
+            ]);
+        }
+        let error = KeyPair::from_pkcs12_der(&pfx.to_der(), b"secret").unwrap_err().to_string();
+        assert!(error.contains("legacy algorithm SHA-1 MAC"), "{}", error);
+
+        let other = CONFIG_TEST.get().generate_key()?;
+        assert!(key_pair.to_pkcs12_der(Some(&fake_certificate(&KeyData::new_public(other.public, "".to_string()))?), b"secret", 1000).is_err());
+        Ok(())
+    }
+}
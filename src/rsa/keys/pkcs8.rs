@@ -0,0 +1,130 @@
+use num_traits::ToPrimitive;
+use zeroize::{Zeroize, Zeroizing};
+use crate::rsa::asn1::{Der, oid_string};
+use crate::rsa::keys::KeyError;
+use crate::rsa::keys::key_data::{KeyData, MODE_PRIVATE, pem_footer, pem_header};
+
+pub const PEM_PUBLIC: &str = "PUBLIC KEY";
+pub const PEM_PRIVATE: &str = "PRIVATE KEY";
+
+/// rsaEncryption, PKCS#1
+pub const OID_RSA_ENCRYPTION: &[u64] = &[1, 2, 840, 113549, 1, 1, 1];
+
+/// Key algorithms that show up in the wild, to name them in errors
+const OID_NAMES: &[(&[u64], &str)] = &[
+    (&[1, 2, 840, 113549, 1, 1, 10], "RSASSA-PSS"),
+    (&[1, 2, 840, 10040, 4, 1], "DSA"),
+    (&[1, 2, 840, 10045, 2, 1], "EC"),
+    (&[1, 3, 101, 110], "X25519"),
+    (&[1, 3, 101, 111], "X448"),
+    (&[1, 3, 101, 112], "Ed25519"),
+    (&[1, 3, 101, 113], "Ed448"),
+];
+
+fn rsa_algorithm() -> Der {
+    Der::Sequence(vec![Der::Oid(OID_RSA_ENCRYPTION.to_vec()), Der::Null])
+}
+
+/// Accept only rsaEncryption, whose parameters must be NULL or absent
+fn check_algorithm(algorithm: &Der) -> Result<(), KeyError> {
+    let items = algorithm.as_sequence()?;
+    let oid = items.first().ok_or_else(|| KeyError::ParseError("PKCS#8: empty AlgorithmIdentifier".to_string()))?.as_oid()?;
+    if oid != OID_RSA_ENCRYPTION {
+        let name = OID_NAMES.iter().find(|(o, _)| *o == oid).map(|(_, name)| *name).unwrap_or("unknown");
+        return Err(KeyError::ParseError(format!("unsupported key algorithm {} ({}), only rsaEncryption ({}) is supported",
+                                                name, oid_string(oid), oid_string(OID_RSA_ENCRYPTION))));
+    }
+    match &items[1..] {
+        [] | [Der::Null] => Ok(()),
+        _ => Err(KeyError::ParseError("PKCS#8: rsaEncryption parameters must be NULL".to_string())),
+    }
+}
+
+impl KeyData {
+    /// PEM label of this key in PKCS#8 or X.509
+    pub fn pkcs8_label(&self) -> &'static str {
+        match self.mode.as_str() {
+            MODE_PRIVATE => PEM_PRIVATE,
+            _ => PEM_PUBLIC,
+        }
+    }
+
+    /// Encode as PKCS#8 `PrivateKeyInfo` (RFC 5208) or X.509 `SubjectPublicKeyInfo` (RFC 5280)
+    pub fn to_pkcs8_der(&self) -> Result<Zeroizing<Vec<u8>>, KeyError> {
+        let inner = self.to_pkcs1_der()?;
+        let der = match self.mode.as_str() {
+            MODE_PRIVATE => Der::Sequence(vec![Der::Integer(0.into()), rsa_algorithm(), Der::OctetString(inner.to_vec())]),
+            _ => Der::Sequence(vec![rsa_algorithm(), Der::BitString(inner.to_vec())]),
+        };
+        let data = Zeroizing::new(der.to_der());
+        // the tree holds a copy of the PKCS#1 bytes
+        if let Der::Sequence(mut items) = der {
+            if let Some(Der::OctetString(inner) | Der::BitString(inner)) = items.last_mut() { inner.zeroize(); }
+        }
+        Ok(data)
+    }
+
+    /// Decode `PrivateKeyInfo` or `SubjectPublicKeyInfo` holding an RSA key
+    pub fn from_pkcs8_der(data: &[u8]) -> Result<KeyData, KeyError> {
+        let der = Der::from_der(data)?;
+        let items = der.as_sequence()?;
+        let mut key_data = match items {
+            [algorithm, key] => {
+                check_algorithm(algorithm)?;
+                KeyData::from_pkcs1_der(key.as_bit_string()?)?
+            }
+            // Version 1 is `OneAsymmetricKey` of RFC 5958, which adds optional trailing fields
+            [version, algorithm, key, ..] => {
+                match version.as_integer()?.to_u32() {
+                    Some(0 | 1) => {}
+                    _ => return Err(KeyError::ParseError("PKCS#8: unsupported PrivateKeyInfo version".to_string())),
+                }
+                check_algorithm(algorithm)?;
+                KeyData::from_pkcs1_der(key.as_octet_string()?)?
+            }
+            _ => return Err(KeyError::ParseError("PKCS#8: expect PrivateKeyInfo or SubjectPublicKeyInfo".to_string())),
+        };
+        let public = items.len() == 2;
+        if public == (key_data.mode == MODE_PRIVATE) {
+            return Err(KeyError::ParseError("PKCS#8: key type does not match its container".to_string()));
+        }
+        let label = key_data.pkcs8_label();
+        key_data.header = pem_header(label);
+        key_data.footer = pem_footer(label);
+        Ok(key_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::rsa::asn1::Der;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::key_data::{KeyData, KeyFormat};
+    use crate::rsa::keys::key_pair::KeyPair;
+
+    #[test]
+    fn test_pkcs8() -> Result<(), Box<dyn Error>> {
+        let key_set = CONFIG_TEST.get().generate_key()?;
+        let mut key_pair = KeyPair {
+            public: KeyData::new_public(key_set.public, "".to_string()),
+            private: KeyData::new_private(key_set.private, "".to_string()),
+        };
+        key_pair.save_format("data/test_pkcs8".to_string(), &KeyFormat::Pkcs8, true)?;
+        let loaded = KeyPair::try_from("data/test_pkcs8".to_string())?;
+        assert_eq!(loaded.public, key_pair.public);
+        assert_eq!(loaded.private.key.crt, key_pair.private.key.crt);
+        key_pair.save_format("data/test_pkcs8_der".to_string(), &KeyFormat::Pkcs8, false)?;
+        let loaded = KeyPair::try_from("data/test_pkcs8_der".to_string())?;
+        assert_eq!(loaded.public.header, "-----BEGIN PUBLIC KEY-----");
+        assert_eq!(loaded.private.key.crt, key_pair.private.key.crt);
+        let ec = Der::Sequence(vec![
+            Der::Sequence(vec![Der::Oid(vec![1, 2, 840, 10045, 2, 1]), Der::Oid(vec![1, 2, 840, 10045, 3, 1, 7])]),
+            Der::BitString(vec![4; 65]),
+        ]).to_der();
+        let err = KeyData::from_pkcs8_der(&ec).unwrap_err().to_string();
+        println!("{}", err);
+        assert!(err.contains("EC (1.2.840.10045.2.1)"));
+        Ok(())
+    }
+}
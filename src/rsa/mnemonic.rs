@@ -0,0 +1,102 @@
+use sha2::{Digest, Sha256};
+use crate::rsa::keys::KeyError;
+
+/// Length of a generated seed in bytes
+pub const SEED_BYTES: usize = 32;
+
+/// One word per byte, the first 4 letters of every word are unique
+const WORDS: [&str; 256] = [
+    "able", "acid", "acorn", "actor", "adapt", "admit", "adult", "agent", "aisle", "alarm", "album",
+    "alert", "alley", "alpha", "amber", "angle", "ankle", "apple", "april", "arena", "arrow",
+    "aspen", "atlas", "attic", "autumn", "awake", "badge", "bagel", "baker", "bamboo", "banjo",
+    "barrel", "basil", "beach", "beard", "bench", "berry", "bicycle", "birch", "bison", "blade",
+    "blanket", "blossom", "bottle", "bounce", "brave", "bread", "brick", "bridge", "bronze",
+    "brush", "bucket", "bundle", "butter", "cabin", "cactus", "camel", "candle", "canoe", "canyon",
+    "carbon", "carpet", "castle", "cedar", "cello", "chalk", "cherry", "chess", "chimney", "cider",
+    "circus", "citrus", "clover", "cobalt", "coconut", "comet", "copper", "coral", "cotton",
+    "cousin", "coyote", "crane", "crater", "crystal", "dagger", "daisy", "dancer", "delta",
+    "desert", "diamond", "dolphin", "donkey", "dragon", "drum", "eagle", "echo", "eclipse", "elbow",
+    "ember", "emerald", "engine", "falcon", "feather", "fence", "fiddle", "fig", "flame", "flute",
+    "forest", "fossil", "fountain", "fox", "galaxy", "garden", "garlic", "gecko", "giant", "ginger",
+    "glacier", "globe", "goblet", "gravel", "guitar", "hammer", "harbor", "harvest", "hazel",
+    "helmet", "hermit", "honey", "hornet", "igloo", "island", "ivory", "jacket", "jaguar",
+    "jasmine", "jelly", "jungle", "kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "lemon",
+    "lens", "lily", "lizard", "lobster", "locket", "lotus", "magnet", "mango", "maple", "marble",
+    "meadow", "melon", "meteor", "mirror", "mitten", "monkey", "mosaic", "moss", "motor", "mustard",
+    "napkin", "nectar", "needle", "nest", "nickel", "noodle", "oasis", "ocean", "olive", "onion",
+    "orbit", "orchid", "otter", "oyster", "paddle", "panda", "pebble", "pelican", "pepper", "piano",
+    "pickle", "pigeon", "pillow", "pine", "planet", "plum", "pocket", "pony", "puzzle", "quartz",
+    "quill", "rabbit", "radar", "radish", "raven", "ribbon", "river", "robin", "rocket", "saddle",
+    "salmon", "sandal", "scarf", "shadow", "shell", "silver", "sketch", "sleeve", "socket",
+    "spider", "spruce", "squid", "statue", "stove", "summit", "sunset", "swan", "tablet", "tango",
+    "temple", "thistle", "thunder", "tiger", "timber", "tomato", "topaz", "torch", "trumpet",
+    "tulip", "tunnel", "turtle", "valley", "velvet", "violin", "volcano", "wagon", "walnut",
+    "walrus", "wheat", "whistle", "willow", "window", "winter", "wizard", "yacht", "yogurt",
+    "zebra", "zephyr", "zigzag", "zinc",
+];
+
+fn checksum(seed: &[u8]) -> u8 {
+    Sha256::digest(seed)[0]
+}
+
+/// Words for `seed`, followed by one checksum word
+pub fn seed_to_mnemonic(seed: &[u8]) -> String {
+    seed.iter().chain([checksum(seed)].iter())
+        .map(|b| WORDS[*b as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decode a mnemonic back to its seed, words may be shortened to their first 4 letters
+pub fn mnemonic_to_seed(mnemonic: &str) -> Result<Vec<u8>, KeyError> {
+    let mut bytes = mnemonic.split_whitespace().map(|word| {
+        let word = word.to_lowercase();
+        WORDS.iter().position(|w| *w == word || (word.len() == 4 && w.starts_with(&word)))
+            .map(|i| i as u8)
+            .ok_or_else(|| KeyError::ParseError(format!("Unknown mnemonic word `{}'", word)))
+    }).collect::<Result<Vec<_>, _>>()?;
+    let sum = bytes.pop().ok_or_else(|| KeyError::ParseError("Empty mnemonic".to_string()))?;
+    if bytes.is_empty() || checksum(&bytes) != sum {
+        return Err(KeyError::ParseError("Mnemonic checksum mismatch".to_string()));
+    }
+    Ok(bytes)
+}
+
+pub fn random_seed() -> Vec<u8> {
+    let mut seed = vec![0; SEED_BYTES];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut seed);
+    seed
+}
+
+/// Parse a hex seed like `3f0a...'
+pub fn parse_seed(seed: &str) -> Result<Vec<u8>, KeyError> {
+    let seed = seed.trim();
+    if seed.is_empty() || !seed.is_ascii() || !seed.len().is_multiple_of(2) {
+        return Err(KeyError::ParseError("Seed must be an even number of hex digits".to_string()));
+    }
+    (0..seed.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&seed[i..i + 2], 16)
+            .map_err(|_| KeyError::ParseError(format!("Seed `{}' is not hex", seed))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rsa::mnemonic::{mnemonic_to_seed, parse_seed, random_seed, seed_to_mnemonic};
+
+    #[test]
+    fn test_mnemonic() {
+        println!("random mnemonic: {}", seed_to_mnemonic(&random_seed()));
+        let seed = (0..32).collect::<Vec<u8>>();
+        let mnemonic = seed_to_mnemonic(&seed);
+        println!("mnemonic: {}", mnemonic);
+        assert_eq!(seed, mnemonic_to_seed(&mnemonic).unwrap());
+        let short = mnemonic.split(' ').map(|w| &w[..4]).collect::<Vec<_>>().join(" ");
+        assert_eq!(seed, mnemonic_to_seed(&short).unwrap());
+        let mut words = mnemonic.split(' ').collect::<Vec<_>>();
+        words.swap(0, 1);
+        assert!(mnemonic_to_seed(&words.join(" ")).is_err());
+        assert_eq!(parse_seed("00ff10").unwrap(), vec![0, 0xff, 0x10]);
+        assert!(parse_seed("0g").is_err());
+    }
+}
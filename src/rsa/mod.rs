@@ -2,27 +2,66 @@ use std::error::Error;
 use std::fs::File;
 use std::{io, thread};
 use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
 use num::Integer;
 use clap::Parser;
 use crossbeam_channel::{bounded, Receiver, Sender};
-use num_bigint::{BigInt, Sign, ToBigInt, ToBigUint};
+use num_bigint::{BigInt, BigUint, Sign, ToBigInt, ToBigUint};
 use num_traits::{One, Pow, Zero};
 use indicatif::{ProgressBar, ProgressStyle};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
 
 pub mod config;
 pub mod prime_gen;
 pub mod keys;
+pub mod asn1;
+pub mod recover;
+pub mod mnemonic;
+pub mod passphrase;
+pub mod safe_file;
+pub mod keyring;
 
 use config::*;
 use keys::*;
+use safe_file::{check_overwrite, Output, SafeFile};
 use prime_gen::*;
 
+type ChunkJob = (usize, Zeroizing<Vec<u8>>, RunMode);
+
+/// Where key generation takes its primes from, called with the range to pick in
+type PrimeSource<'a> = dyn FnMut(&BigUint, &BigUint) -> Result<BigInt, PrimeError> + 'a;
+
+/// F4, the public exponent used outside of lab mode
+pub const PUBLIC_EXPONENT: u32 = 65537;
+
+/// Smallest prime size outside of lab mode, smaller ranges hold too few primes to pick distinct ones
+pub const MIN_PRIME_BITS: usize = 16;
+
 #[derive(Debug, Clone)]
 pub enum RunMode {
     Generate,
     Encode,
     Decode,
     Test,
+    Check,
+    Recover,
+    Assemble,
+    Export,
+    Mnemonic,
+    Passwd,
+    Migrate,
+    KeyringList,
+    KeyringImport,
+    KeyringExport,
+    KeyringDelete,
+    KeyringSetDefault,
+    Pkcs12Export,
+    Pkcs12Import,
+    PaperExport,
+    PaperRestore,
 }
 
 #[macro_export]
@@ -32,24 +71,74 @@ macro_rules! rsa_t {
 pub struct $NAME {
     #[clap(short, long, value_parser, default_value = $CONFIG.mode.as_str(), help = "Run mode")]
     pub mode: String,
-    #[clap(short, long, value_parser, default_value = $CONFIG.key.as_str(), help = "Key path, generate/detect `path' and `path.pub'")]
+    #[clap(short, long, value_parser, default_value = $CONFIG.key.as_str(), help = "Key path, generate/detect `path' and `path.pub', or a keyring key as @name, @<fingerprint prefix> or @ for the default")]
     pub key: String,
+    #[clap(long, value_parser, default_value = $CONFIG.keyring.as_str(), help = "Keyring directory, ~/.rsa-rs/keyring when empty")]
+    pub keyring: String,
+    #[clap(long, value_parser, default_value = $CONFIG.name.as_str(), help = "Name of the key imported into the keyring, the file name of --key by default")]
+    pub name: String,
     #[clap(short, long, value_parser, default_value = $CONFIG.comment.as_str(), help = "Attach comment to key files")]
     pub comment: String,
-    #[clap(long, value_parser, default_value_t = $CONFIG.binary, help = "Output key in binary format")]
+    #[clap(long, value_parser, default_value_t = $CONFIG.binary, help = "Output key in binary format, DER for standard formats")]
     pub binary: bool,
+    #[clap(long, value_parser, default_value = $CONFIG.format.as_str(), help = "Key file format to write: native, pkcs1, pkcs8, openssh, jwk, jwks")]
+    pub format: String,
     #[clap(short, long, value_parser, default_value = $CONFIG.input.as_str(), help = "Input filename")]
     pub input: String,
     #[clap(short, long, value_parser, default_value = $CONFIG.output.as_str(), help = "Output filename")]
     pub output: String,
-    #[clap(long, value_parser, required = false, default_value_t = $CONFIG.prime_min, help = "Min prime bits")]
+    #[clap(long, value_parser, default_value_t = $CONFIG.bits, help = "Modulus size in bits, e.g. 1024, 2048, 3072, 4096")]
+    pub bits: usize,
+    #[clap(long, value_parser, default_value_t = $CONFIG.min_bits, help = "Refuse to generate keys smaller than <MIN_BITS> bits")]
+    pub min_bits: usize,
+    #[clap(long, value_parser, default_value_t = $CONFIG.lab, help = "Lab mode, pick prime sizes randomly between --prime-min and --prime-max bits")]
+    pub lab: bool,
+    #[clap(long, value_parser, required = false, default_value_t = $CONFIG.prime_min, help = "Min prime bits, lab mode only")]
     pub prime_min: u32,
-    #[clap(long, value_parser, required = false, default_value_t = $CONFIG.prime_max, help = "Max prime bits")]
+    #[clap(long, value_parser, required = false, default_value_t = $CONFIG.prime_max, help = "Max prime bits, lab mode only")]
     pub prime_max: u32,
+    #[clap(long, value_parser, default_value_t = $CONFIG.primes, help = "Count of primes in the modulus, more than 2 for multi-prime RSA")]
+    pub primes: usize,
     #[clap(short, long, value_parser, default_value_t = $CONFIG.rounds, help = "Miller Rabin calculate rounds")]
     pub rounds: u32,
     #[clap(long, value_parser, default_value_t = $CONFIG.time_max, help = "Max time in mill seconds that trying to generate a prime")]
     pub time_max: i64,
+    #[clap(long, value_parser, default_value = $CONFIG.passphrase_env.as_str(), help = "Read the key passphrase from this environment variable instead of the terminal")]
+    pub passphrase_env: String,
+    #[clap(long, value_parser, help = "Read the key passphrase from the first line of this file descriptor")]
+    pub passphrase_fd: Option<i32>,
+    #[clap(long, value_parser, default_value = $CONFIG.new_passphrase_env.as_str(), help = "Read the new passphrase of modes `passwd' and `pkcs12-import', or the password of mode `pkcs12-export', from this environment variable")]
+    pub new_passphrase_env: String,
+    #[clap(long, value_parser, default_value_t = $CONFIG.no_passphrase, help = "Save the key of modes `passwd' and `pkcs12-import' without a passphrase")]
+    pub no_passphrase: bool,
+    #[clap(long, value_parser, default_value = $CONFIG.seed.as_str(), help = "Derive keys from this hex seed instead of random numbers")]
+    pub seed: String,
+    #[clap(long, value_parser, default_value = $CONFIG.mnemonic.as_str(), help = "Derive keys from the seed in this mnemonic, see mode `mnemonic'")]
+    pub mnemonic: String,
+    #[clap(long, value_parser, default_value = $CONFIG.modulus.as_str(), help = "Modulus n to assemble a key from, decimal or 0x hex")]
+    pub modulus: String,
+    #[clap(long, value_parser, default_value = $CONFIG.public_exponent.as_str(), help = "Public exponent e to assemble a key from, decimal or 0x hex")]
+    pub public_exponent: String,
+    #[clap(long, value_parser, default_value = $CONFIG.private_exponent.as_str(), help = "Private exponent d to assemble a key from, decimal or 0x hex")]
+    pub private_exponent: String,
+    #[clap(long, value_parser, help = "Prime to assemble a key from, decimal or 0x hex, repeat for every prime")]
+    pub prime: Vec<String>,
+    #[clap(long, value_parser, default_value = $CONFIG.number_format.as_str(), help = "Export key components as dec, hex or json")]
+    pub number_format: String,
+    #[clap(long, value_parser, default_value = $CONFIG.cert.as_str(), help = "X.509 certificate, PEM or DER, put into the file of mode `pkcs12-export'; mode `pkcs12-import' saves the one it finds there, to `<KEY>.crt' when empty")]
+    pub cert: String,
+    #[clap(long, value_parser, default_value = $CONFIG.paper_encoding.as_str(), help = "Write the lines of mode `paper-export' as hex or base32")]
+    pub paper_encoding: String,
+    #[clap(long, value_parser, default_value = $CONFIG.owner.as_str(), help = "Owner identity saved with new keys, e.g. an email address")]
+    pub owner: String,
+    #[clap(long, value_parser, default_value = $CONFIG.expires.as_str(), help = "Expiry saved with new keys: YYYY-MM-DD, an RFC 3339 time or days like 90d, never when empty")]
+    pub expires: String,
+    #[clap(long, value_parser, default_value = $CONFIG.usages.as_str(), help = "Usages allowed for new keys, comma separated: encrypt, decrypt, sign, verify")]
+    pub usages: String,
+    #[clap(long, value_parser, default_value_t = $CONFIG.ignore_metadata, help = "Use keys even when expired or not marked for the operation")]
+    pub ignore_metadata: bool,
+    #[clap(long, value_parser, default_value_t = $CONFIG.force, help = "Overwrite existing key and output files")]
+    pub force: bool,
     #[clap(short, long, value_parser, default_value_t = $CONFIG.silent, help = "Disable log output")]
     pub silent: bool,
     #[clap(long, value_parser, default_value_t = $CONFIG.retry, help = "Retry when failed to generate primes")]
@@ -69,19 +158,56 @@ impl RSA {
 
     pub fn copy(&self) -> RSA {
         RSA {
+            bits: self.bits,
+            min_bits: self.min_bits,
+            lab: self.lab,
             prime_min: self.prime_min,
             prime_max: self.prime_max,
+            primes: self.primes,
             input: self.input.clone(),
             output: self.output.clone(),
             binary: self.binary,
+            format: self.format.clone(),
             rounds: self.rounds,
             time_max: self.time_max,
             mode: self.mode.clone(),
             silent: self.silent,
             key: self.key.clone(),
+            keyring: self.keyring.clone(),
+            name: self.name.clone(),
             threads: self.threads,
             retry: self.retry,
             comment: self.comment.clone(),
+            passphrase_env: self.passphrase_env.clone(),
+            passphrase_fd: self.passphrase_fd,
+            new_passphrase_env: self.new_passphrase_env.clone(),
+            no_passphrase: self.no_passphrase,
+            seed: self.seed.clone(),
+            mnemonic: self.mnemonic.clone(),
+            modulus: self.modulus.clone(),
+            public_exponent: self.public_exponent.clone(),
+            private_exponent: self.private_exponent.clone(),
+            prime: self.prime.clone(),
+            number_format: self.number_format.clone(),
+            cert: self.cert.clone(),
+            paper_encoding: self.paper_encoding.clone(),
+            owner: self.owner.clone(),
+            expires: self.expires.clone(),
+            usages: self.usages.clone(),
+            ignore_metadata: self.ignore_metadata,
+            force: self.force,
+        }
+    }
+
+    /// Copy for logging, with the options that carry key material blanked out
+    pub fn redacted(&self) -> RSA {
+        let hide = |value: &String| if value.is_empty() { String::new() } else { "<redacted>".to_string() };
+        RSA {
+            seed: hide(&self.seed),
+            mnemonic: hide(&self.mnemonic),
+            private_exponent: hide(&self.private_exponent),
+            prime: self.prime.iter().map(hide).collect(),
+            ..self.copy()
         }
     }
 
@@ -96,38 +222,104 @@ impl RSA {
         }
     }
 
-    pub fn writer(&mut self) -> Box<dyn Write> {
+    /// `--output`, a file is written aside and only appears once `Output::finish` is called
+    pub fn writer(&mut self) -> Result<Output, Box<dyn Error>> {
+        self.output_writer(false)
+    }
+
+    /// `--output` like `writer`, a file is readable by the owner only when `private`
+    fn output_writer(&mut self, private: bool) -> Result<Output, Box<dyn Error>> {
         match self.output.as_str() {
             "stdout" => {
                 self.silent = true;
-                Box::new(io::stdout())
+                Ok(Output::Stdout(io::stdout()))
+            }
+            f => {
+                check_overwrite(f, self.force).map_err(|e| e.to_string())?;
+                Ok(Output::File(SafeFile::create(f, private).map_err(|e| format!("{}: {}", f, e))?))
             }
-            f => Box::new(File::create(f).unwrap())
         }
     }
 
+    /// Refuse to replace the key files of `--key` unless `--force` is given
+    fn check_key_overwrite(&self) -> Result<(), Box<dyn Error>> {
+        for path in [self.key.clone(), self.key.clone() + ".pub"] {
+            check_overwrite(&path, self.force).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
     fn run_mode(&self) -> RunMode {
         match self.mode.as_str() {
             "encode" => Ok(RunMode::Encode),
             "decode" => Ok(RunMode::Decode),
             "generate" => Ok(RunMode::Generate),
             "test" => Ok(RunMode::Test),
-            _ => Err("Unknown run mode! available: generate(default), encode, decode, test")
+            "check" => Ok(RunMode::Check),
+            "recover" => Ok(RunMode::Recover),
+            "assemble" => Ok(RunMode::Assemble),
+            "export" => Ok(RunMode::Export),
+            "mnemonic" => Ok(RunMode::Mnemonic),
+            "passwd" => Ok(RunMode::Passwd),
+            "migrate" => Ok(RunMode::Migrate),
+            "keyring-list" => Ok(RunMode::KeyringList),
+            "keyring-import" => Ok(RunMode::KeyringImport),
+            "keyring-export" => Ok(RunMode::KeyringExport),
+            "keyring-delete" => Ok(RunMode::KeyringDelete),
+            "keyring-set-default" => Ok(RunMode::KeyringSetDefault),
+            "pkcs12-export" => Ok(RunMode::Pkcs12Export),
+            "pkcs12-import" => Ok(RunMode::Pkcs12Import),
+            "paper-export" => Ok(RunMode::PaperExport),
+            "paper-restore" => Ok(RunMode::PaperRestore),
+            _ => Err("Unknown run mode! available: generate(default), encode, decode, test, check, recover, assemble, export, mnemonic, passwd, migrate, keyring-list, keyring-import, keyring-export, keyring-delete, keyring-set-default, pkcs12-export, pkcs12-import, paper-export, paper-restore")
         }.unwrap()
     }
 
+    pub fn key_format(&self) -> Result<KeyFormat, KeyError> {
+        KeyFormat::try_from(self.format.as_str())
+    }
+
+    /// Metadata for keys created now from `--owner`, `--expires` and `--usages`.
+    /// Usages that allow everything are left out, which lets JWK output carry `use` in place of `key_ops`.
+    pub fn key_metadata(&self) -> Result<KeyMetadata, KeyError> {
+        let now = chrono::Utc::now().timestamp();
+        let expires = match self.expires.is_empty() {
+            true => None,
+            false => Some(parse_expiry(&self.expires, now)?),
+        };
+        let usages = parse_usages(&self.usages)?;
+        let usages = match KeyUsage::ALL.iter().all(|u| usages.contains(u)) {
+            true => None,
+            false => Some(usages),
+        };
+        Ok(KeyMetadata { created: Some(now), expires, owner: self.owner.clone(), usages })
+    }
+
+    /// Refuse `key` for `usage` when it expired or is not marked for it, only warn with `--ignore-metadata`
+    fn check_metadata(&self, key: &KeyData, usage: KeyUsage) -> Result<(), KeyError> {
+        match key.metadata.check(usage, chrono::Utc::now().timestamp()) {
+            Err(e) if self.ignore_metadata => {
+                if !self.silent { println!("Warning: {}, ignored", e); }
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
     pub fn euler(p: &BigInt, q: &BigInt) -> BigInt { (p - 1.to_bigint().unwrap()) * (q - 1.to_bigint().unwrap()) }
 
-    fn extended_euclid(a: &BigInt, b: &BigInt, x: &BigInt, y: &BigInt) -> (BigInt, BigInt, BigInt) {
+    pub fn euler_primes(primes: &[BigInt]) -> BigInt { primes.iter().map(|p| p - 1.to_bigint().unwrap()).product() }
+
+    fn extended_euclid(a: &BigInt, b: &BigInt) -> (BigInt, BigInt, BigInt) {
         if b.is_zero() {
             return (a.clone(), 1.to_bigint().unwrap(), 0.to_bigint().unwrap());
         }
-        let (d, x2, y2) = RSA::extended_euclid(b, &(a % b), y, x);
-        return (d, y2.clone(), x2 - a / b * &y2);
+        let (d, x2, y2) = RSA::extended_euclid(b, &(a % b));
+        (d, y2.clone(), x2 - a / b * &y2)
     }
 
     pub fn mod_reverse(a: &BigInt, b: &BigInt) -> BigInt {
-        let d = RSA::extended_euclid(a, b, &Zero::zero(), &One::one());
+        let d = RSA::extended_euclid(a, b);
         if d.0.is_one() {
             (d.1 % b + b) % b
         } else {
@@ -136,48 +328,102 @@ impl RSA {
     }
 
     pub fn generate_key(&self) -> Result<KeySet, PrimeError> {
+        self.generate_key_primes(self.primes)
+    }
+
+    /// Generate a key with `count` primes, RFC 8017 multi-prime RSA when `count > 2`
+    pub fn generate_key_primes(&self, count: usize) -> Result<KeySet, PrimeError> {
+        self.generate_key_from(count, &mut |low, high| self.generate_prime(low, high))
+    }
+
+    /// Derive a key from `seed`, the same seed and parameters always give the same keys
+    pub fn generate_key_seeded(&self, seed: &[u8]) -> Result<KeySet, PrimeError> {
+        let mut rng = ChaCha20Rng::from_seed(Sha256::digest(seed).into());
+        let rounds = self.rounds;
+        self.generate_key_from(self.primes, &mut |low, high| Ok(RSA::generate_prime_rng(low, high, rounds, &mut rng)))
+    }
+
+    fn generate_key_from(&self, count: usize, next_prime: &mut PrimeSource) -> Result<KeySet, PrimeError> {
+        if count < 2 { return Err(PrimeError::Key(KeyError::InvalidKey("RSA needs at least 2 primes".to_string()))); }
+        if self.lab { return self.generate_key_lab_from(count, next_prime); }
+        if self.bits < self.min_bits { return Err(PrimeError::KeySize(self.bits, self.min_bits)); }
+        if self.bits / count < MIN_PRIME_BITS { return Err(PrimeError::PrimeSize(self.bits / count, MIN_PRIME_BITS)); }
+        let e = BigInt::from(PUBLIC_EXPONENT);
+        let primes = loop {
+            let mut primes: Vec<BigInt> = Vec::new();
+            for i in 0..count {
+                // top two bits set, so that the product of two primes keeps every bit
+                let bits = self.bits / count + usize::from(i < self.bits % count);
+                let low = BigUint::from(3u32) << (bits - 2);
+                let high = BigUint::one() << bits;
+                loop {
+                    let prime = next_prime(&low, &high)?;
+                    if !primes.contains(&prime) && (&prime - 1.to_bigint().unwrap()).gcd(&e).is_one() {
+                        primes.push(prime);
+                        break;
+                    }
+                }
+            }
+            if primes.iter().product::<BigInt>().bits() as usize == self.bits { break primes; }
+        };
+        RSA::key_set_from_primes(primes, e)
+    }
+
+    /// Old lab behaviour: prime sizes are random in `prime_min..prime_max` bits and e is a random prime
+    pub fn generate_key_lab(&self, count: usize) -> Result<KeySet, PrimeError> {
+        self.generate_key_lab_from(count, &mut |low, high| self.generate_prime(low, high))
+    }
+
+    fn generate_key_lab_from(&self, count: usize, next_prime: &mut PrimeSource) -> Result<KeySet, PrimeError> {
+        if !self.silent { println!("Warning: lab mode generates keys of unpredictable size, do not use them for real data"); }
+        // keep the modulus near the size of a two prime key
+        let prime_max = (self.prime_max * 2 / count as u32).max(self.prime_min + 1);
         let low = 2.to_biguint().unwrap().pow(self.prime_min);
-        let high = 2.to_biguint().unwrap().pow(self.prime_max);
-        let (p, q) = (self.generate_prime(&low, &high)?, self.generate_prime(&low, &high)?);
-        let n = &p * &q;
-        let f = RSA::euler(&p, &q);
+        let high = 2.to_biguint().unwrap().pow(prime_max);
+        let mut primes: Vec<BigInt> = Vec::new();
+        while primes.len() < count {
+            let prime = next_prime(&low, &high)?;
+            if !primes.contains(&prime) { primes.push(prime); }
+        }
+        let f = RSA::euler_primes(&primes);
         let mut e;
         loop {
-            e = self.generate_prime(&1.to_biguint().unwrap(), &f.to_biguint().unwrap())?;
+            e = next_prime(&1.to_biguint().unwrap(), &f.to_biguint().unwrap())?;
             if f.gcd(&e).is_one() { break; }
         }
+        RSA::key_set_from_primes(primes, e)
+    }
+
+    fn key_set_from_primes(primes: Vec<BigInt>, e: BigInt) -> Result<KeySet, PrimeError> {
+        let n = primes.iter().product::<BigInt>();
+        let f = RSA::euler_primes(&primes);
         let d = RSA::mod_reverse(&e, &f);
-        self.check_key_set(&d, &e, &f);
-        Ok(KeySet { public: Key { m: n.clone(), base: e }, private: Key { m: n.clone(), base: d } })
+        RSA::check_key_set(&d, &e, &f)?;
+        let crt = KeyCrt::new(&e, &d, &primes)?;
+        Ok(KeySet { public: Key::new(e, n.clone()), private: Key { m: n, base: d, crt: Some(crt) } })
     }
 
-    pub fn check_key_set(&self, d: &BigInt, e: &BigInt, f: &BigInt) {
-        let res = (d * e) % f;
-        if !self.silent {
-            println!("(d * e) % f = {} % {} = {}", d * e, f, res);
+    /// Whether d is the inverse of e modulo f, without printing either since they give away the private key
+    pub fn check_key_set(d: &BigInt, e: &BigInt, f: &BigInt) -> Result<(), KeyError> {
+        match ((d * e) % f).is_one() {
+            true => Ok(()),
+            false => Err(KeyError::InvalidKey("d * e != 1 mod f".to_string())),
         }
-        assert!(res.is_one());
     }
 
-    pub fn read_source(reader: &mut dyn Read, bytes: usize) -> Vec<u8> {
-        let mut source = [0 as u8; 1];
-        let mut res = Vec::new();
-        loop {
-            match reader.read(source.as_mut()) {
-                Ok(n) => match n {
-                    0 => break,
-                    _ => {
-                        res.push(source[0]);
-                        if res.len() >= bytes { break; }
-                    }
-                },
-                _ => break
-            }
+    pub fn read_source(reader: &mut dyn Read, bytes: usize) -> Zeroizing<Vec<u8>> {
+        let mut source = [0_u8; 1];
+        // Sized up front so that no reallocation leaves a copy of the plaintext behind
+        let mut res = Zeroizing::new(Vec::with_capacity(bytes));
+        while let Ok(n) = reader.read(source.as_mut()) {
+            if n == 0 { break; }
+            res.push(source[0]);
+            if res.len() >= bytes { break; }
         }
         res
     }
 
-    fn get_group_size_byte(n: &BigInt) -> usize { f64::pow(2 as f64, ((n.bits() as usize / 8) as f64).log2().ceil()) as usize / 2 }
+    fn get_group_size_byte(n: &BigInt) -> usize { f64::pow(2_f64, ((n.bits() as usize / 8) as f64).log2().ceil()) as usize / 2 }
 
     pub fn process(reader: &mut dyn Read, writer: &mut dyn Write, mode: RunMode, key: Key, threads: usize, silent: bool) {
         let group_size = RSA::get_group_size_byte(&key.m) * match mode {
@@ -193,10 +439,10 @@ impl RSA {
             _ => group_size / 2
         };
         if !silent { println!("group size {}, input => output: {} => {}", group_size, source_len_target, res_len_target); }
-        let mut source_data: Vec<Vec<u8>> = Vec::new();
+        let mut source_data: Vec<Zeroizing<Vec<u8>>> = Vec::new();
         let mut filesize_data = match mode {
             RunMode::Decode => {
-                let mut t = [0 as u8; 8];
+                let mut t = [0_u8; 8];
                 let n = reader.read(&mut t).unwrap();
                 assert_eq!(n, 8, "Too small file!");
                 u64::from_le_bytes(t)
@@ -214,7 +460,7 @@ impl RSA {
             filesize_data = filesize_read;
         }
         if !silent { println!("source chunk: {}", chunks); }
-        let (map_tx, map_rx): (Sender<(usize, Key, Vec<u8>, RunMode)>, Receiver<(usize, Key, Vec<u8>, RunMode)>) = bounded(threads);
+        let (map_tx, map_rx): (Sender<ChunkJob>, Receiver<ChunkJob>) = bounded(threads);
         let (reduce_tx, reduce_rx) = bounded(threads);
         let pb = match silent {
             true => None,
@@ -225,51 +471,44 @@ impl RSA {
                 .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})").unwrap()
                 .progress_chars("#>-"));
         }
+        // Shared rather than cloned per chunk, so the private key exists once and is wiped once
+        let key = Arc::new(key);
         let handles = (0..threads).map(|_i| {
             let r = map_rx.clone();
             let s = reduce_tx.clone();
+            let key = key.clone();
             thread::spawn(move || {
-                loop {
-                    match r.recv() {
-                        Ok(r) => {
-                            let (index, key, source, mode) = r;
-                            let data = BigInt::from_bytes_le(Sign::Plus, source.as_slice());
-                            let res = RSA::fast_modular_exponent(data.clone(), key.base.clone(), key.m.clone());
-                            let mut res_data = res.to_bytes_le().1.clone();
-                            let res_data_len = res_data.len();
-                            match mode {
-                                RunMode::Encode | RunMode::Decode => {
-                                    let fill = res_len_target - res_data_len;
-                                    if fill != 0 && chunks != index + 1 {
-                                        // println!("fill {} bytes", fill);
-                                        for _ in 0..fill { res_data.push(0); }
-                                    }
-                                }
-                                _ => {}
-                            };
-                            if chunks != index + 1 { assert_eq!(res_len_target, res_data.len()); }
-                            s.send((index, res_data)).unwrap();
+                while let Ok(r) = r.recv() {
+                    let (index, source, mode) = r;
+                    let data = BigInt::from_bytes_le(Sign::Plus, source.as_slice());
+                    let mut res = key.modular_exponent(data);
+                    let bytes = Zeroizing::new(res.to_bytes_le().1);
+                    keys::wipe(&mut res);
+                    let mut res_data = Zeroizing::new(Vec::with_capacity(res_len_target.max(bytes.len())));
+                    res_data.extend_from_slice(&bytes);
+                    match mode {
+                        RunMode::Encode | RunMode::Decode if res_data.len() < res_len_target && chunks != index + 1 => {
+                            res_data.resize(res_len_target, 0);
                         }
-                        _ => break
-                    }
+                        _ => {}
+                    };
+                    if chunks != index + 1 { assert_eq!(res_len_target, res_data.len()); }
+                    s.send((index, res_data)).unwrap();
                 }
             })
         }).collect::<Vec<_>>();
         let mut res_collect = Vec::new();
-        for i in 0..source_data.len() {
-            match reduce_rx.try_recv() {
-                Ok(r) => {
-                    res_collect.push(r);
-                    if let Some(pb) = &pb {
-                        pb.inc(group_size as u64);
-                    }
+        for (i, source) in source_data.into_iter().enumerate() {
+            if let Ok(r) = reduce_rx.try_recv() {
+                res_collect.push(r);
+                if let Some(pb) = &pb {
+                    pb.inc(group_size as u64);
                 }
-                _ => {}
             };
-            map_tx.send((i, key.clone(), source_data[i].clone(), mode.clone())).unwrap();
+            map_tx.send((i, source, mode.clone())).unwrap();
         }
         drop(map_tx);
-        let left = source_data.len() - res_collect.len();
+        let left = chunks - res_collect.len();
         for _ in 0..left {
             let r = reduce_rx.recv().unwrap();
             res_collect.push(r);
@@ -282,135 +521,316 @@ impl RSA {
         }
         for handle in handles { handle.join().unwrap(); }
         res_collect.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-        for i in 0..res_collect.len() {
-            assert_eq!(i, res_collect[i].0);
+        for (i, r) in res_collect.iter().enumerate() {
+            assert_eq!(i, r.0);
         }
-        assert_eq!(res_collect.len(), source_data.len());
+        assert_eq!(res_collect.len(), chunks);
         if !silent { println!("read filesize: {filesize_read}, data filesize: {filesize_data} res chunk: {}", res_collect.len()); }
-        let res_collect = res_collect.iter().map(|x| x.1.clone()).collect::<Vec<_>>();
-        match mode {
-            RunMode::Encode => {
-                writer.write(&filesize_data.to_le_bytes()).unwrap();
-            }
-            _ => {}
+        let res_collect = res_collect.into_iter().map(|x| x.1).collect::<Vec<_>>();
+        if let RunMode::Encode = mode {
+            writer.write_all(&filesize_data.to_le_bytes()).unwrap();
         };
         for res_data in &res_collect {
-            writer.write(&res_data).unwrap();
+            writer.write_all(res_data).unwrap();
         }
-        match mode {
-            RunMode::Decode => for _ in 0..(filesize_data - res_collect.iter().map(|v| v.len()).sum::<usize>() as u64) {
-                writer.write(&[0 as u8; 1]).unwrap();
-            },
-            _ => {}
+        if let RunMode::Decode = mode {
+            for _ in 0..(filesize_data - res_collect.iter().map(|v| v.len()).sum::<usize>() as u64) {
+                writer.write_all(&[0_u8; 1]).unwrap();
+            }
         };
         writer.flush().unwrap();
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.run_mode() {
+            RunMode::KeyringList | RunMode::KeyringImport | RunMode::KeyringExport | RunMode::KeyringDelete | RunMode::KeyringSetDefault => {}
+            _ => self.resolve_key()?,
+        }
         match self.run_mode() {
             RunMode::Generate => {
-                let key_set = self.generate_key()?;
-                if !self.silent { println!("get keys: {:?}", key_set); }
+                self.check_key_overwrite()?;
+                let key_set = match (self.seed.is_empty(), self.mnemonic.is_empty()) {
+                    (true, true) => self.generate_key()?,
+                    (false, true) => self.generate_key_seeded(&mnemonic::parse_seed(&self.seed)?)?,
+                    (true, false) => self.generate_key_seeded(&mnemonic::mnemonic_to_seed(&self.mnemonic)?)?,
+                    (false, false) => return Err("Use only one of --seed and --mnemonic".into()),
+                };
                 let mut key_pair = KeyPair {
                     public: KeyData::new_public(key_set.public, self.comment.clone()),
                     private: KeyData::new_private(key_set.private, self.comment.clone()),
                 };
-                key_pair.private.generate_header_footer_bits(self.prime_max as usize);
-                key_pair.public.generate_header_footer_bits(self.prime_max as usize);
-                if !self.silent { println!("get key_pair: {:?}", key_pair); }
-                key_pair.save(self.key.clone(), !self.binary).unwrap();
-                if !self.silent { println!("Generated key files: {}, {}", self.key.clone(), self.key.clone() + ".pub"); }
+                key_pair.set_metadata(self.key_metadata()?);
+                let bits = if self.lab { self.prime_max as usize } else { self.bits };
+                key_pair.private.generate_header_footer_bits(bits);
+                key_pair.public.generate_header_footer_bits(bits);
+                let passphrase = self.new_passphrase(&self.passphrase_env, self.passphrase_fd)?;
+                self.save_key_pair(&mut key_pair, &passphrase)?;
+                if !self.silent {
+                    println!("Generated key files: {}, {}", self.key.clone(), self.key.clone() + ".pub");
+                    key_pair.public.print_fingerprint();
+                }
             }
             RunMode::Test => {
-                let key_pair = KeyPair::from(self.key.clone());
-                if key_pair.public == KeyData::default() || key_pair.private == KeyData::default() {
-                    let key = if key_pair.public == KeyData::default() {
-                        key_pair.private
-                    } else {
-                        key_pair.public
-                    };
-                    if !self.silent { print!("key infomation: "); }
-                    key.info();
-                } else {
-                    key_pair.public.info();
-                    key_pair.private.info();
-                    if !self.silent { println!("start testing key pair"); }
-                    if !self.silent { println!("get key_pair: {:?}", key_pair); }
-                    assert_eq!(key_pair.public.key.m, key_pair.private.key.m);
-                    let group_size = RSA::get_group_size_byte(&key_pair.public.key.m);
-                    let res_len_target = |mode| match mode {
-                        RunMode::Encode => 2 * group_size,
-                        _ => group_size
-                    };
-                    let mut reader = if self.input != "stdin" { self.reader() } else { Box::new(File::open("/dev/random").unwrap()) };
-                    let max_source_len = 1000;
-                    let mut source_data: Vec<Vec<u8>> = Vec::new();
-                    for _ in 0..max_source_len {
-                        let source = RSA::read_source(&mut reader, group_size);
-                        if source.is_empty() { break; }
-                        source_data.push(source);
+                let key_pair = match self.load_keys()? {
+                    (Some(public), Some(private)) => KeyPair { public, private },
+                    (Some(key), None) | (None, Some(key)) => {
+                        if !self.silent { print!("key infomation: "); }
+                        key.info();
+                        return Ok(());
                     }
-                    let pb = match self.silent {
-                        true => None,
-                        false => Some(ProgressBar::new((source_data.len() * group_size) as u64)),
-                    };
+                    (None, None) => unreachable!(),
+                };
+                key_pair.public.info();
+                key_pair.private.info();
+                if !self.silent { println!("start testing key pair"); }
+                if !self.silent { println!("get key_pair: {:?}", key_pair); }
+                assert_eq!(key_pair.public.key.m, key_pair.private.key.m);
+                let group_size = RSA::get_group_size_byte(&key_pair.public.key.m);
+                let res_len_target = |mode| match mode {
+                    RunMode::Encode => 2 * group_size,
+                    _ => group_size
+                };
+                let mut reader = if self.input != "stdin" { self.reader() } else { Box::new(File::open("/dev/random").unwrap()) };
+                let max_source_len = 1000;
+                let mut source_data: Vec<Zeroizing<Vec<u8>>> = Vec::new();
+                for _ in 0..max_source_len {
+                    let source = RSA::read_source(&mut reader, group_size);
+                    if source.is_empty() { break; }
+                    source_data.push(source);
+                }
+                let pb = match self.silent {
+                    true => None,
+                    false => Some(ProgressBar::new((source_data.len() * group_size) as u64)),
+                };
+                if let Some(pb) = &pb {
+                    pb.set_style(ProgressStyle::default_bar()
+                        .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})").unwrap()
+                        .progress_chars("#>-"));
+                }
+                let mut file_writer = if self.output != "stdout" {
+                    Some(self.writer()?)
+                } else { None };
+                for source in source_data {
+                    let m = BigInt::from_bytes_le(Sign::Plus, &source);
+                    let c = RSA::fast_modular_exponent(m.clone(), key_pair.public.key.base.clone(), key_pair.public.key.m.clone());
+                    let m2 = key_pair.private.key.modular_exponent(c.clone());
+                    assert_eq!(m, m2);
+                    let mut buf: Vec<u8> = Vec::new();
+                    let mut writer = Cursor::new(&mut buf);
+                    writer.write_all(&c.to_bytes_le().1).unwrap();
+                    let buf_len = (c.bits() as f64 / 8.0).ceil() as usize;
+                    for _ in 0..(res_len_target(RunMode::Encode) - buf_len) { writer.write_all(&[0]).unwrap(); }
+                    writer.flush().unwrap();
+                    assert_eq!(2 * group_size, buf.len());
+                    let c2 = BigInt::from_bytes_le(Sign::Plus, &buf);
+                    assert_eq!(c, c2);
+                    let m3 = key_pair.private.key.modular_exponent(c2.clone());
+                    assert_eq!(m2, m3);
+                    assert_eq!(m2.to_bytes_le().1, m3.to_bytes_le().1);
+                    let mut buf: Vec<u8> = Vec::new();
+                    let mut writer = Cursor::new(&mut buf);
+                    writer.write_all(&m3.to_bytes_le().1).unwrap();
+                    let buf_len = (m3.bits() as f64 / 8.0).ceil() as usize;
+                    for _ in 0..(res_len_target(RunMode::Decode) - buf_len) { writer.write_all(&[0]).unwrap(); }
+                    writer.flush().unwrap();
+                    assert_eq!(*source, buf);
                     if let Some(pb) = &pb {
-                        pb.set_style(ProgressStyle::default_bar()
-                            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})").unwrap()
-                            .progress_chars("#>-"));
+                        pb.inc(group_size as u64);
                     }
-                    let mut file_writer = if self.output != "stdout" {
-                        Some(Box::new(File::create(self.output.as_str()).unwrap()))
-                    } else { None };
-                    for source in source_data {
-                        let m = BigInt::from_bytes_le(Sign::Plus, &source);
-                        let c = RSA::fast_modular_exponent(m.clone(), key_pair.public.key.base.clone(), key_pair.public.key.m.clone());
-                        let m2 = RSA::fast_modular_exponent(c.clone(), key_pair.private.key.base.clone(), key_pair.private.key.m.clone());
-                        assert_eq!(m, m2);
-                        let mut buf: Vec<u8> = Vec::new();
-                        let mut writer = Cursor::new(&mut buf);
-                        writer.write_all(&c.to_bytes_le().1).unwrap();
-                        let buf_len = (c.bits() as f64 / 8.0).ceil() as usize;
-                        for _ in 0..(res_len_target(RunMode::Encode) - buf_len as usize) { writer.write(&[0]).unwrap(); }
-                        writer.flush().unwrap();
-                        assert_eq!(2 * group_size, buf.len());
-                        let c2 = BigInt::from_bytes_le(Sign::Plus, &buf);
-                        assert_eq!(c, c2);
-                        let m3 = RSA::fast_modular_exponent(c2.clone(), key_pair.private.key.base.clone(), key_pair.private.key.m.clone());
-                        assert_eq!(m2, m3);
-                        assert_eq!(m2.to_bytes_le().1, m3.to_bytes_le().1);
-                        let mut buf: Vec<u8> = Vec::new();
-                        let mut writer = Cursor::new(&mut buf);
-                        writer.write_all(&m3.to_bytes_le().1).unwrap();
-                        let buf_len = (m3.bits() as f64 / 8.0).ceil() as usize;
-                        for _ in 0..(res_len_target(RunMode::Decode) - buf_len as usize) { writer.write(&[0]).unwrap(); }
-                        writer.flush().unwrap();
-                        assert_eq!(source, buf);
-                        if let Some(pb) = &pb {
-                            pb.inc(group_size as u64);
-                        }
-                        if let Some(file_writer) = &mut file_writer {
-                            file_writer.write_all(&buf).unwrap();
-                            file_writer.flush().unwrap();
-                        }
+                    if let Some(file_writer) = &mut file_writer {
+                        file_writer.write_all(&buf).unwrap();
+                        file_writer.flush().unwrap();
                     }
-                    if let Some(pb) = &pb {
-                        pb.finish_with_message("Test pass");
+                }
+                if let Some(file_writer) = file_writer { file_writer.finish()?; }
+                if let Some(pb) = &pb {
+                    pb.finish_with_message("Test pass");
+                }
+                if !self.silent { println!("Test pass"); };
+            }
+            RunMode::Check => {
+                let report = match self.load_keys()? {
+                    (Some(public), Some(private)) => KeyPair { public, private }.check(self.rounds),
+                    (Some(key), None) | (None, Some(key)) => key.check(self.rounds),
+                    (None, None) => unreachable!(),
+                };
+                report.print();
+                if !report.passed() { return Err("Key check failed".into()); }
+            }
+            RunMode::Recover => {
+                let (mut private, format, passphrase) = self.load_key_keeping_passphrase(self.key.clone())?;
+                if private.mode != MODE_PRIVATE {
+                    return Err(format!("No private key found at {}", self.key).into());
+                }
+                if private.key.crt.is_some() {
+                    if !self.silent { println!("Private key already carries its primes"); }
+                    return Ok(());
+                }
+                let public = self.load_key(self.key.clone() + ".pub")
+                    .map_err(|e| format!("Public exponent is needed from {}.pub: {}", self.key, e))?;
+                if public.key.m != private.key.m {
+                    return Err(format!("Public key {}.pub does not belong to the private key", self.key).into());
+                }
+                private.key.recover_crt(&public.key.base, self.rounds)?;
+                self.save_private_as(&mut private, &format, &passphrase)?;
+                if !self.silent { println!("Recovered primes and saved full private key: {}", self.key); }
+            }
+            RunMode::Passwd => {
+                let (mut key, format) = self.load_key_with_format(self.key.clone())?;
+                if key.mode != MODE_PRIVATE {
+                    return Err(format!("No private key found at {}", self.key).into());
+                }
+                let passphrase = self.replacement_passphrase()?;
+                self.save_private_as(&mut key, &format, &passphrase)?;
+                if !self.silent {
+                    println!("{} {}", if passphrase.is_some() { "Encrypted private key" } else { "Saved private key without passphrase" }, self.key);
+                }
+            }
+            RunMode::Migrate => {
+                let mut found = false;
+                for path in [self.key.clone(), self.key.clone() + ".pub"] {
+                    match KeyData::native_version(&path)? {
+                        Some(NATIVE_LEGACY_VERSION) => {
+                            let mut key = self.load_key(path.clone())?;
+                            let base64_output = !key.binary;
+                            key.save(path.clone(), base64_output)?;
+                            if !self.silent { println!("Migrated {} to native version {}", path, NATIVE_VERSION); }
+                        }
+                        Some(version) => if !self.silent { println!("{} is already native version {}", path, version); },
+                        None => continue,
                     }
-                    if !self.silent { println!("Test pass"); };
+                    found = true;
                 }
+                if !found { return Err(format!("No native key file found at {}", self.key).into()); }
+            }
+            RunMode::Mnemonic => {
+                let seed = mnemonic::random_seed();
+                println!("mnemonic: {}", mnemonic::seed_to_mnemonic(&seed));
+                println!("seed: {}", seed.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+            }
+            RunMode::Assemble => {
+                self.check_key_overwrite()?;
+                let component = |value: &String| match value.is_empty() {
+                    true => Ok(None),
+                    false => parse_component(value).map(Some),
+                };
+                let components = KeyComponents {
+                    n: component(&self.modulus)?,
+                    e: component(&self.public_exponent)?,
+                    d: component(&self.private_exponent)?,
+                    primes: self.prime.iter().map(|p| parse_component(p)).collect::<Result<Vec<_>, _>>()?,
+                };
+                let mut key_pair = KeyPair::from_components(&components, self.comment.clone(), self.rounds)?;
+                key_pair.set_metadata(self.key_metadata()?);
+                let passphrase = self.new_passphrase(&self.passphrase_env, self.passphrase_fd)?;
+                self.save_key_pair(&mut key_pair, &passphrase)?;
+                if !self.silent { println!("Assembled key files: {}, {}", self.key.clone(), self.key.clone() + ".pub"); }
+            }
+            RunMode::Export => {
+                let format = NumberFormat::try_from(self.number_format.as_str())?;
+                let key = match self.load_keys()? {
+                    (_, Some(private)) => private,
+                    (Some(public), None) => public,
+                    (None, None) => unreachable!(),
+                };
+                let mut writer = self.output_writer(true)?;
+                writer.write_all(key.export_components(&format).as_bytes())?;
+                writer.finish()?;
             }
             RunMode::Encode | RunMode::Decode => {
                 let mut reader = self.reader();
-                let mut writer = self.writer();
+                let mut writer = self.writer()?;
                 let path = match self.run_mode() {
                     RunMode::Decode => self.key.clone(),
                     _ => self.key.clone() + ".pub"
                 };
-                let key = KeyData::from(path);
+                let (key, format) = self.load_key_with_format(path.clone())?;
+                if !self.silent { println!("Loaded key {} in {} format", path, format); }
+                self.check_metadata(&key, match self.run_mode() {
+                    RunMode::Decode => KeyUsage::Decrypt,
+                    _ => KeyUsage::Encrypt,
+                })?;
                 RSA::process(&mut reader, &mut writer, self.run_mode(), key.key, self.threads, self.silent);
+                writer.finish()?;
                 if !self.silent { println!("Done"); };
             }
+            RunMode::KeyringList => self.keyring_list()?,
+            RunMode::KeyringImport => {
+                let entry = self.keyring_import()?;
+                if !self.silent { println!("Imported {} as {}", entry.fingerprint_openssh(), entry.name); }
+            }
+            RunMode::KeyringExport => {
+                let entry = self.keyring_export()?;
+                if !self.silent && self.output != "stdout" { println!("Exported {} to {}", entry.name, self.output); }
+            }
+            RunMode::KeyringDelete => {
+                let entry = self.keyring()?.remove(self.keyring_reference())?;
+                if !self.silent { println!("Deleted {} {}", entry.name, entry.fingerprint_openssh()); }
+            }
+            RunMode::KeyringSetDefault => {
+                let entry = self.keyring()?.set_default(self.keyring_reference())?;
+                if !self.silent { println!("Default key is now {} {}", entry.name, entry.fingerprint_openssh()); }
+            }
+            RunMode::Pkcs12Export => {
+                let private = self.load_key(self.key.clone())?;
+                if private.mode != MODE_PRIVATE {
+                    return Err(format!("No private key found at {}", self.key).into());
+                }
+                let key_pair = KeyPair { public: self.load_key(self.key.clone() + ".pub")?, private };
+                let certificate = match self.cert.is_empty() {
+                    true => None,
+                    false => Some(certificate_der(&std::fs::read(&self.cert).map_err(|e| format!("{}: {}", self.cert, e))?)?),
+                };
+                let password = self.new_passphrase(&self.new_passphrase_env, None)?
+                    .ok_or("PKCS#12 files need a password, give it with --new-passphrase-env or on a terminal")?;
+                let der = key_pair.to_pkcs12_der(certificate.as_deref(), &password, PBKDF2_ITERATIONS)?;
+                let mut writer = self.output_writer(true)?;
+                writer.write_all(&der)?;
+                writer.finish()?;
+                if !self.silent { println!("Exported {} to {}", self.key, self.output); }
+            }
+            RunMode::Pkcs12Import => {
+                self.check_key_overwrite()?;
+                let cert_path = match self.cert.is_empty() {
+                    true => self.key.clone() + ".crt",
+                    false => self.cert.clone(),
+                };
+                let mut data = vec![];
+                self.reader().read_to_end(&mut data)?;
+                let password = Zeroizing::new(self.read_passphrase_for(&self.input)?);
+                let (mut key_pair, certificate) = KeyPair::from_pkcs12_der(&data, &password)?;
+                if certificate.is_some() { check_overwrite(&cert_path, self.force).map_err(|e| e.to_string())?; }
+                key_pair.set_metadata(self.key_metadata()?);
+                let passphrase = self.replacement_passphrase()?;
+                self.save_key_pair(&mut key_pair, &passphrase)?;
+                if !self.silent { println!("Imported key files: {}, {}", self.key.clone(), self.key.clone() + ".pub"); }
+                if let Some(certificate) = certificate {
+                    let mut file = SafeFile::create(&cert_path, false).map_err(|e| format!("{}: {}", cert_path, e))?;
+                    file.write_all(certificate_pem(&certificate)?.as_bytes())?;
+                    file.commit()?;
+                    if !self.silent { println!("Saved certificate: {}", cert_path); }
+                }
+            }
+            RunMode::PaperExport => {
+                let encoding = PaperEncoding::try_from(self.paper_encoding.as_str())?;
+                let paper = self.load_key(self.key.clone())?.to_paper(&encoding)?;
+                let mut writer = self.output_writer(true)?;
+                writer.write_all(paper.as_bytes())?;
+                writer.finish()?;
+            }
+            RunMode::PaperRestore => {
+                self.check_key_overwrite()?;
+                let mut text = Zeroizing::new(String::new());
+                self.reader().read_to_string(&mut text)?;
+                let mut key_pair = KeyPair::from_paper(&text, self.comment.clone(), self.rounds)?;
+                key_pair.set_metadata(self.key_metadata()?);
+                let passphrase = self.new_passphrase(&self.passphrase_env, self.passphrase_fd)?;
+                self.save_key_pair(&mut key_pair, &passphrase)?;
+                if !self.silent {
+                    println!("Restored key files: {}, {}", self.key.clone(), self.key.clone() + ".pub");
+                    key_pair.public.print_fingerprint();
+                }
+            }
         }
         Ok(())
     }
@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::{IsTerminal, Read};
+use zeroize::Zeroizing;
+use crate::rsa::keys::{KeyData, KeyError, KeyFormat, KeyPair};
+use crate::RSA;
+
+/// Passphrase a loaded key was encrypted with, wiped when dropped
+type KeptPassphrase = Option<Zeroizing<Vec<u8>>>;
+
+/// First line of what `reader` holds, without the line break
+fn first_line(reader: &mut dyn Read) -> Result<Vec<u8>, KeyError> {
+    let mut data = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut data).map_err(|e| KeyError::Passphrase(e.to_string()))?;
+    let line = data.split(|b| *b == b'\n').next().unwrap_or_default();
+    Ok(line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+}
+
+fn prompt(message: &str) -> Result<Vec<u8>, KeyError> {
+    rpassword::prompt_password(message)
+        .map(|p| p.into_bytes())
+        .map_err(|e| KeyError::Passphrase(format!("cannot read passphrase from the terminal: {}", e)))
+}
+
+/// Passphrase from the environment variable `env` or the first line of file descriptor `fd`, if either is given
+fn from_source(env: &str, fd: Option<i32>) -> Option<Result<Vec<u8>, KeyError>> {
+    if !env.is_empty() {
+        return Some(std::env::var(env).map(|p| p.into_bytes())
+            .map_err(|_| KeyError::Passphrase(format!("environment variable {} is not set", env))));
+    }
+    fd.map(|fd| match File::open(format!("/dev/fd/{}", fd)) {
+        Ok(mut file) => first_line(&mut file),
+        Err(e) => Err(KeyError::Passphrase(format!("cannot read file descriptor {}: {}", fd, e))),
+    })
+}
+
+impl RSA {
+    /// Passphrase of an existing key, from `--passphrase-env`, `--passphrase-fd` or the terminal
+    pub fn read_passphrase(&self) -> Result<Vec<u8>, KeyError> {
+        self.read_passphrase_for(&self.key)
+    }
+
+    /// Passphrase of the file `name` from the same sources as `read_passphrase`
+    pub fn read_passphrase_for(&self, name: &str) -> Result<Vec<u8>, KeyError> {
+        from_source(&self.passphrase_env, self.passphrase_fd)
+            .unwrap_or_else(|| prompt(&format!("Enter passphrase for {}: ", name)))
+    }
+
+    /// Passphrase for a new key from `env` or `fd`, `None` to leave the key in the clear.
+    /// Without either it is asked for only on a terminal. It is wiped when dropped.
+    pub fn new_passphrase(&self, env: &str, fd: Option<i32>) -> Result<Option<Zeroizing<Vec<u8>>>, KeyError> {
+        let passphrase = if let Some(passphrase) = from_source(env, fd) {
+            Zeroizing::new(passphrase?)
+        } else if io::stdin().is_terminal() {
+            let passphrase = Zeroizing::new(prompt("Enter passphrase (empty for no passphrase): ")?);
+            if *Zeroizing::new(prompt("Enter same passphrase again: ")?) != *passphrase {
+                return Err(KeyError::Passphrase("passphrases do not match".to_string()));
+            }
+            passphrase
+        } else {
+            Zeroizing::new(vec![])
+        };
+        Ok(match passphrase.is_empty() {
+            true => None,
+            false => Some(passphrase),
+        })
+    }
+
+    /// New passphrase of modes `passwd' and `pkcs12-import', see `new_passphrase`. Saving the key in the clear
+    /// takes `--no-passphrase` or an empty passphrase confirmed on the terminal.
+    pub fn replacement_passphrase(&self) -> Result<Option<Zeroizing<Vec<u8>>>, KeyError> {
+        if self.no_passphrase { return Ok(None); }
+        let terminal = self.new_passphrase_env.is_empty() && io::stdin().is_terminal();
+        match self.new_passphrase(&self.new_passphrase_env, None)? {
+            None if !terminal => Err(KeyError::Passphrase("no new passphrase given, use --new-passphrase-env or --no-passphrase to save the key in the clear".to_string())),
+            passphrase => Ok(passphrase),
+        }
+    }
+
+    pub fn load_key(&self, path: String) -> Result<KeyData, KeyError> {
+        self.load_key_with_format(path).map(|(key, _)| key)
+    }
+
+    /// Key at `path` in whatever format the file is in, and that format
+    pub fn load_key_with_format(&self, path: String) -> Result<(KeyData, KeyFormat), KeyError> {
+        KeyData::load_with_format(path, &mut || self.read_passphrase())
+    }
+
+    /// Key at `path`, its format and the passphrase it is encrypted with, to write it back the way it was stored
+    pub fn load_key_keeping_passphrase(&self, path: String) -> Result<(KeyData, KeyFormat, KeptPassphrase), KeyError> {
+        let mut kept = None;
+        let (key, format) = KeyData::load_with_format(path, &mut || {
+            let passphrase = self.read_passphrase()?;
+            kept = Some(Zeroizing::new(passphrase.clone()));
+            Ok(passphrase)
+        })?;
+        Ok((key, format, kept))
+    }
+
+    /// Public and private key of `--key`, `None` for a missing file as long as one of them exists
+    pub fn load_keys(&self) -> Result<(Option<KeyData>, Option<KeyData>), KeyError> {
+        let found = |key: Result<KeyData, KeyError>| match key {
+            Ok(key) => Ok(Some(key)),
+            Err(KeyError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        };
+        match (found(self.load_key(self.key.clone() + ".pub"))?, found(self.load_key(self.key.clone()))?) {
+            (None, None) => Err(KeyError::NotFound(format!("{}, {}.pub", self.key, self.key))),
+            keys => Ok(keys),
+        }
+    }
+
+    /// Save both keys, the private one encrypted when `passphrase` is given
+    pub fn save_key_pair(&self, key_pair: &mut KeyPair, passphrase: &Option<Zeroizing<Vec<u8>>>) -> Result<(), Box<dyn Error>> {
+        match passphrase {
+            Some(passphrase) => key_pair.save_encrypted(self.key.clone(), &self.key_format()?, !self.binary, passphrase),
+            None => key_pair.save_format(self.key.clone(), &self.key_format()?, !self.binary),
+        }
+    }
+
+    /// Write a loaded private key back to `--key` in `format` and the encoding its file had, encrypted when `passphrase` is given
+    pub fn save_private_as(&self, key: &mut KeyData, format: &KeyFormat, passphrase: &Option<Zeroizing<Vec<u8>>>) -> Result<(), KeyError> {
+        let base64_output = !key.binary;
+        match passphrase {
+            Some(passphrase) => key.save_encrypted(self.key.clone(), format, base64_output, passphrase),
+            None => key.save_format(self.key.clone(), format, base64_output),
+        }
+    }
+}
@@ -5,20 +5,30 @@ use std::thread;
 use chrono::Local;
 use lazy_static::lazy_static;
 use num_bigint::{BigInt, BigUint, RandBigInt, ToBigInt};
+use rand::Rng;
 use num_traits::*;
-use crate::rsa::prime_gen::PrimeError::Timeout;
+use crate::rsa::prime_gen::PrimeError::{Key, KeySize, PrimeSize, Timeout};
+use crate::rsa::keys::{KeyError, wipe};
 use crate::RSA;
 use mut_static::MutStatic;
-use crate::rsa::config::SILENT;
+use crate::rsa::config::silent;
 
 pub enum PrimeError {
-    Timeout(i64)
+    Timeout(i64),
+    KeySize(usize, usize),
+    /// Each prime of the key would get fewer bits than the minimum
+    PrimeSize(usize, usize),
+    /// The generated numbers do not make a valid key
+    Key(KeyError),
 }
 
 impl PrimeError {
     fn display(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Timeout(time) => write!(f, "Generation timeout after {} ms", time)
+            Timeout(time) => write!(f, "Generation timeout after {} ms", time),
+            KeySize(bits, min) => write!(f, "Key size {} bits is below the minimum {} bits, use --min-bits to allow it", bits, min),
+            PrimeSize(bits, min) => write!(f, "Primes of {} bits are below the minimum {} bits, use more --bits or fewer --primes", bits, min),
+            Key(e) => write!(f, "{}", e),
         }
     }
 }
@@ -37,8 +47,30 @@ impl Debug for PrimeError {
 
 impl Error for PrimeError {}
 
+impl From<KeyError> for PrimeError {
+    fn from(e: KeyError) -> Self {
+        Key(e)
+    }
+}
+
 lazy_static! {
-    pub static ref PRIMES_CACHE: MutStatic<Vec<BigInt>> = MutStatic::from(Vec::new());
+    /// Spare primes from the threads of `generate_prime`, with the range they were picked in
+    pub static ref PRIMES_CACHE: MutStatic<Vec<(BigUint, BigUint, CachedPrime)>> = MutStatic::from(Vec::new());
+}
+
+/// A spare prime may become a factor of a later key, so it is wiped when dropped
+pub struct CachedPrime(BigInt);
+
+impl Drop for CachedPrime {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+fn take_cached_prime(low: &BigUint, high: &BigUint) -> Option<BigInt> {
+    let mut cache = PRIMES_CACHE.write().unwrap();
+    let index = cache.iter().position(|(l, h, _)| l == low && h == high)?;
+    Some(std::mem::take(&mut cache.swap_remove(index).2.0))
 }
 
 impl RSA {
@@ -53,9 +85,13 @@ impl RSA {
     }
 
     pub fn miller_rabin(n: &BigInt, rounds: u32) -> Result<bool, Box<dyn Error>> {
-        if n.is_zero() { return Ok(true); }
-        if !n.bit(0) || n.is_one() { return Ok(false); }
-        let mut rng = rand::thread_rng();
+        Ok(RSA::miller_rabin_rng(n, rounds, &mut rand::thread_rng()))
+    }
+
+    /// Miller Rabin test taking its random bases from `rng`
+    pub fn miller_rabin_rng<R: Rng + ?Sized>(n: &BigInt, rounds: u32, rng: &mut R) -> bool {
+        if n.is_zero() { return true; }
+        if !n.bit(0) || n.is_one() { return false; }
         let mut d: BigInt = n - 1.to_bigint().unwrap();
         while d.bit(0) { d >>= 1; }
         let tmp = d.clone();
@@ -74,16 +110,15 @@ impl RSA {
                     m = (&m * &m) % n;
                     d <<= 1;
                 }
-                if !pass { return Ok(false); }
+                if !pass { return false; }
             }
         }
-        Ok(true)
+        true
     }
 
     pub fn generate_prime(&self, low: &BigUint, high: &BigUint) -> Result<BigInt, PrimeError> {
-        if !PRIMES_CACHE.read().unwrap().is_empty() {
-            let prime = PRIMES_CACHE.write().unwrap().pop().unwrap().clone();
-            if !SILENT.read().unwrap().clone() { println!("Use cached prime: {}", prime); }
+        if let Some(prime) = take_cached_prime(low, high) {
+            if !silent() { println!("Use cached prime of {} bits", prime.bits()); }
             return Ok(prime);
         }
         let t: usize = self.threads;
@@ -98,20 +133,13 @@ impl RSA {
             })
         }).collect::<Vec<_>>();
         for _ in 0..t {
-            match rx.recv().unwrap() {
-                Ok(r) => PRIMES_CACHE.write().unwrap().push(r),
-                _ => {}
-            }
+            if let Ok(r) = rx.recv().unwrap() { PRIMES_CACHE.write().unwrap().push((low.clone(), high.clone(), CachedPrime(r))) }
         }
         for handle in handles { handle.join().unwrap(); }
-        if PRIMES_CACHE.read().unwrap().is_empty() {
-            if self.retry {
-                self.generate_prime(low, high)
-            } else {
-                Err(Timeout(self.time_max))
-            }
-        } else {
-            Ok(PRIMES_CACHE.write().unwrap().pop().unwrap())
+        match take_cached_prime(low, high) {
+            Some(prime) => Ok(prime),
+            None if self.retry => self.generate_prime(low, high),
+            None => Err(Timeout(self.time_max))
         }
     }
 
@@ -123,11 +151,11 @@ impl RSA {
         loop {
             try_times += &epoch;
             for _ in 0..epoch {
-                let test = rng.gen_biguint_range(&low, &high).to_bigint().unwrap();
+                let test = rng.gen_biguint_range(low, high).to_bigint().unwrap();
                 if RSA::miller_rabin(&test, rounds).unwrap() {
                     let now = Local::now().timestamp_millis();
                     let time = now - start;
-                    if !SILENT.read().unwrap().clone() {
+                    if !silent() {
                         println!("Done generation in {} tries after {} ms", try_times, time);
                     }
                     return Ok(test);
@@ -136,11 +164,19 @@ impl RSA {
             let now = Local::now().timestamp_millis();
             let time = now - start;
             if time > time_max {
-                if !SILENT.read().unwrap().clone() {
+                if !silent() {
                     println!("Failed generation in {} tries after {} ms", try_times, time);
                 }
                 return Err(Timeout(time));
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Deterministic version of `generate_one_prime`, every random number comes from `rng`
+    pub fn generate_prime_rng<R: Rng + ?Sized>(low: &BigUint, high: &BigUint, rounds: u32, rng: &mut R) -> BigInt {
+        loop {
+            let test = rng.gen_biguint_range(low, high).to_bigint().unwrap();
+            if RSA::miller_rabin_rng(&test, rounds, rng) { return test; }
+        }
+    }
+}
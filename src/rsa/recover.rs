@@ -0,0 +1,106 @@
+use num::Integer;
+use num_bigint::{BigInt, RandBigInt, ToBigInt};
+use num_traits::{One, Zero};
+use crate::rsa::keys::{Key, KeyCrt, KeyError};
+use crate::RSA;
+
+/// Random bases to try before giving up, as NIST SP 800-56B appendix C.2
+const RECOVER_TRIES: usize = 100;
+
+impl RSA {
+    /// Find a non-trivial factor of `n` from `e` and `d`, NIST SP 800-56B appendix C.2
+    fn recover_factor(n: &BigInt, e: &BigInt, d: &BigInt) -> Option<BigInt> {
+        let one = BigInt::one();
+        let n_1 = n - &one;
+        let k: BigInt = d * e - &one;
+        if k.is_zero() || k.is_odd() { return None; }
+        let t = k.trailing_zeros().unwrap();
+        let r = &k >> t;
+        let mut rng = rand::thread_rng();
+        for _ in 0..RECOVER_TRIES {
+            let g = rng.gen_biguint_range(&2u32.into(), &n_1.to_biguint().unwrap()).to_bigint().unwrap();
+            let mut y = RSA::fast_modular_exponent(g, r.clone(), n.clone());
+            if y == one || y == n_1 { continue; }
+            for _ in 0..t {
+                let x = (&y * &y) % n;
+                if x == one {
+                    return Some((&y - &one).gcd(n));
+                }
+                if x == n_1 { break; }
+                y = x;
+            }
+        }
+        None
+    }
+
+    /// Factor `n` into its primes given the public and private exponents
+    pub fn recover_primes(n: &BigInt, e: &BigInt, d: &BigInt, rounds: u32) -> Result<Vec<BigInt>, KeyError> {
+        if n <= &BigInt::from(3) || n.is_even() {
+            return Err(KeyError::InvalidKey("n must be an odd number above 3".to_string()));
+        }
+        if RSA::miller_rabin(n, rounds).unwrap_or(false) {
+            return Err(KeyError::InvalidKey("n is a prime, not a product of primes".to_string()));
+        }
+        let mut factors = vec![n.clone()];
+        let mut primes = Vec::new();
+        while let Some(factor) = factors.pop() {
+            if RSA::miller_rabin(&factor, rounds).unwrap_or(false) {
+                primes.push(factor);
+                continue;
+            }
+            match RSA::recover_factor(&factor, e, d) {
+                Some(p) => {
+                    factors.push(&factor / &p);
+                    factors.push(p);
+                }
+                None => return Err(KeyError::InvalidKey("cannot factor n with these e and d".to_string()))
+            }
+        }
+        primes.sort();
+        Ok(primes)
+    }
+}
+
+impl Key {
+    /// Upgrade a private key holding only d and n to the full form with CRT parameters
+    pub fn recover_crt(&mut self, e: &BigInt, rounds: u32) -> Result<(), KeyError> {
+        let mut primes = RSA::recover_primes(&self.m, e, &self.base, rounds)?;
+        // larger prime first, as OpenSSL does
+        primes.reverse();
+        self.crt = Some(KeyCrt::new(e, &self.base, &primes)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_bigint::BigInt;
+    use crate::rsa::config::CONFIG_TEST;
+    use crate::rsa::keys::KeyCrt;
+    use crate::RSA;
+
+    #[test]
+    fn test_recover_primes() -> Result<(), Box<dyn Error>> {
+        let r = CONFIG_TEST.get();
+        for count in 2..4 {
+            let keys = r.generate_key_primes(count)?;
+            let crt = keys.private.crt.clone().unwrap();
+            let mut expected = crt.primes();
+            expected.sort();
+            let primes = RSA::recover_primes(&keys.private.m, &keys.public.base, &keys.private.base, r.rounds)?;
+            assert_eq!(expected, primes);
+            let mut legacy = keys.private.clone();
+            legacy.crt = None;
+            legacy.recover_crt(&keys.public.base, r.rounds)?;
+            let m = BigInt::from(0x114514);
+            assert_eq!(m, legacy.modular_exponent(keys.public.modular_exponent(m.clone())));
+        }
+        let (e, d) = (BigInt::from(3), BigInt::from(3));
+        for n in [0, 1, 2, 3, 4, 7] {
+            assert!(RSA::recover_primes(&BigInt::from(n), &e, &d, r.rounds).is_err());
+        }
+        assert!(KeyCrt::new(&e, &d, &[BigInt::from(7)]).is_err());
+        Ok(())
+    }
+}
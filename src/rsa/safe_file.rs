@@ -0,0 +1,170 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+lazy_static! {
+    /// Temp files not renamed into place yet, removed when the process is interrupted
+    static ref PENDING: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+}
+
+static WATCH_SIGNALS: Once = Once::new();
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Remove the pending temp files on Ctrl-C or SIGTERM, then exit as the signal would
+fn watch_signals() {
+    WATCH_SIGNALS.call_once(|| {
+        if let Ok(mut signals) = Signals::new([SIGINT, SIGTERM]) {
+            std::thread::spawn(move || {
+                if let Some(signal) = signals.forever().next() {
+                    for path in PENDING.lock().unwrap().drain(..) {
+                        let _ = fs::remove_file(path);
+                    }
+                    std::process::exit(128 + signal);
+                }
+            });
+        }
+    });
+}
+
+fn forget(path: &Path) {
+    PENDING.lock().unwrap().retain(|p| p != path);
+}
+
+/// `path` when it does not exist yet or `force` is given, an `AlreadyExists` error otherwise
+pub fn check_overwrite(path: &str, force: bool) -> io::Result<()> {
+    match !force && Path::new(path).exists() {
+        true => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists, use --force to overwrite it", path))),
+        false => Ok(()),
+    }
+}
+
+/// A file written next to its destination and renamed over it by `commit`, so readers never see half of it.
+/// Dropping it uncommitted, on an error or a panic, removes what was written.
+pub struct SafeFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl SafeFile {
+    /// Start writing `path`, readable by the owner only when `private`
+    pub fn create(path: impl AsRef<Path>, private: bool) -> io::Result<Self> {
+        watch_signals();
+        let path = path.as_ref().to_path_buf();
+        let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display())))?;
+        let temp = path.with_file_name(format!(".{}.{}.{}.tmp", name.to_string_lossy(), std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        #[cfg(not(unix))]
+        let _ = private;
+        PENDING.lock().unwrap().push(temp.clone());
+        let file = options.open(&temp).inspect_err(|_| forget(&temp))?;
+        Ok(Self { path, temp, file, committed: false })
+    }
+
+    /// Flush to disk and move the file into place
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()?;
+        fs::rename(&self.temp, &self.path)?;
+        self.committed = true;
+        forget(&self.temp);
+        Ok(())
+    }
+}
+
+impl Write for SafeFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for SafeFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+            forget(&self.temp);
+        }
+    }
+}
+
+/// Where `--output` goes, a file only appears once `finish` is called
+pub enum Output {
+    Stdout(io::Stdout),
+    File(SafeFile),
+}
+
+impl Output {
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush(),
+            Output::File(file) => file.commit(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::fs;
+    use std::io::Write;
+    use crate::rsa::safe_file::{check_overwrite, SafeFile};
+
+    #[test]
+    fn test_safe_file() -> Result<(), Box<dyn Error>> {
+        let path = "data/test_safe_file";
+        let _ = fs::remove_file(path);
+        check_overwrite(path, false)?;
+        let mut file = SafeFile::create(path, true)?;
+        file.write_all(b"first")?;
+        assert!(fs::metadata(path).is_err());
+        file.commit()?;
+        assert_eq!(fs::read(path)?, b"first");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(path)?.permissions().mode() & 0o777, 0o600);
+        }
+        assert!(check_overwrite(path, false).is_err());
+        check_overwrite(path, true)?;
+        let mut file = SafeFile::create(path, false)?;
+        file.write_all(b"second")?;
+        drop(file);
+        assert_eq!(fs::read(path)?, b"first");
+        assert!(fs::read_dir("data")?.all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with(".test_safe_file")));
+        Ok(())
+    }
+}